@@ -0,0 +1,241 @@
+//! Rigid obstacles moving under the forces of the flow.
+//!
+//! Bodies live on the host: the force and moment measured on their obstacle during a step
+//! accelerate them, and the compute shader rasterizes them at their new pose before the next
+//! collide-and-stream pass. Fluid cells a body covers become its wall cells and hand their
+//! momentum to it, cells it uncovers are refilled with fluid moving along with its surface.
+//! All quantities are in lattice units with a time step of one.
+
+use crate::forces::ObstacleForce;
+
+/// Most vertices a polygonal body can have, the body buffer of the shader has room for this
+/// many.
+pub const MAX_BODY_VERTICES: usize = 16;
+
+/// Number of `vec4`s per body in the body buffer: pose, outline and motion, followed by two
+/// vertices per `vec4`.
+pub(crate) const BODY_STRIDE: usize = 3 + MAX_BODY_VERTICES / 2;
+
+// Transitional type of cells a body uncovered, refilled during the next collide-and-stream
+// pass and turned into fluid when the bodies move again.
+pub(crate) const WALL_TO_FLUID: u8 = 12;
+
+/// Shape of a body relative to its center of mass, before it is rotated, in cells.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Outline {
+    Circle { radius: f32 },
+    Polygon { points: Vec<[f32; 2]> },
+}
+
+impl Outline {
+    /// Moves a polygon given in absolute positions to its centroid, which is returned along
+    /// with it.
+    pub fn polygon(points: &[[f32; 2]]) -> ([f32; 2], Self) {
+        let mut area = 0.0;
+        let mut moment = [0.0, 0.0];
+
+        for (i, a) in points.iter().enumerate() {
+            let b = points[(i + 1) % points.len()];
+            let cross = a[0] * b[1] - b[0] * a[1];
+
+            area += 0.5 * cross;
+            moment[0] += (a[0] + b[0]) * cross / 6.0;
+            moment[1] += (a[1] + b[1]) * cross / 6.0;
+        }
+
+        let centroid = [moment[0] / area, moment[1] / area];
+
+        let points = points
+            .iter()
+            .map(|point| [point[0] - centroid[0], point[1] - centroid[1]])
+            .collect();
+
+        (centroid, Outline::Polygon { points })
+    }
+
+    pub fn area(&self) -> f32 {
+        match self {
+            Outline::Circle { radius } => std::f32::consts::PI * radius * radius,
+            Outline::Polygon { points } => polygon_sums(points).0.abs(),
+        }
+    }
+
+    /// Moment of inertia about the center of mass per unit of mass, for a uniform density.
+    pub fn specific_inertia(&self) -> f32 {
+        match self {
+            Outline::Circle { radius } => 0.5 * radius * radius,
+            Outline::Polygon { points } => {
+                let (area, inertia) = polygon_sums(points);
+
+                inertia / area
+            }
+        }
+    }
+
+    /// Whether a point relative to the center of mass lies inside, with the same rules as
+    /// [`Shape::contains`](crate::scene::Shape::contains).
+    pub fn contains(&self, point: [f32; 2]) -> bool {
+        match self {
+            Outline::Circle { radius } => {
+                (point[0] * point[0] + point[1] * point[1]).sqrt() < *radius
+            }
+            Outline::Polygon { points } => {
+                let mut inside = false;
+
+                for (i, a) in points.iter().enumerate() {
+                    let b = points[(i + 1) % points.len()];
+
+                    if (a[1] > point[1]) != (b[1] > point[1]) {
+                        let x = a[0] + (point[1] - a[1]) / (b[1] - a[1]) * (b[0] - a[0]);
+
+                        if point[0] < x {
+                            inside = !inside;
+                        }
+                    }
+                }
+
+                inside
+            }
+        }
+    }
+}
+
+/// Signed area and polar moment of area about the origin of a polygon.
+fn polygon_sums(points: &[[f32; 2]]) -> (f32, f32) {
+    let mut area = 0.0;
+    let mut inertia = 0.0;
+
+    for (i, a) in points.iter().enumerate() {
+        let b = points[(i + 1) % points.len()];
+        let cross = a[0] * b[1] - b[0] * a[1];
+
+        area += 0.5 * cross;
+        inertia += cross
+            * (a[0] * a[0] + a[0] * b[0] + b[0] * b[0] + a[1] * a[1] + a[1] * b[1] + b[1] * b[1])
+            / 12.0;
+    }
+
+    (area, inertia)
+}
+
+/// A rigid obstacle with its pose and motion.
+#[derive(Clone, Debug, PartialEq)]
+pub struct RigidBody {
+    pub outline: Outline,
+    /// Index of the obstacle the body is rasterized as, its forces move the body.
+    pub obstacle: usize,
+    /// Center of mass, in cells.
+    pub position: [f32; 2],
+    pub velocity: [f32; 2],
+    /// Rotation of the outline, positive angles turn from `+x` towards `+y`, clockwise on
+    /// screen.
+    pub angle: f32,
+    pub angular_velocity: f32,
+    /// Mass per unit depth, the fluid has a density of about one.
+    pub mass: f32,
+    /// Moment of inertia about the center of mass.
+    pub inertia: f32,
+    /// Fixed point the body turns about instead of moving freely, e.g. the hinge of a flapping
+    /// plate.
+    pub pivot: Option<[f32; 2]>,
+}
+
+impl RigidBody {
+    /// A body of uniform density at rest.
+    pub fn new(outline: Outline, position: [f32; 2], mass: f32, obstacle: usize) -> Self {
+        let inertia = mass * outline.specific_inertia();
+
+        Self {
+            outline,
+            obstacle,
+            position,
+            velocity: [0.0, 0.0],
+            angle: 0.0,
+            angular_velocity: 0.0,
+            mass,
+            inertia,
+            pivot: None,
+        }
+    }
+
+    /// Whether the cell at `point` belongs to the body.
+    pub fn contains(&self, point: [f32; 2]) -> bool {
+        let d = [point[0] - self.position[0], point[1] - self.position[1]];
+        let (sin, cos) = self.angle.sin_cos();
+
+        self.outline
+            .contains([cos * d[0] + sin * d[1], cos * d[1] - sin * d[0]])
+    }
+
+    /// Velocity of the body at `point`, including its rotation.
+    pub fn surface_velocity(&self, point: [f32; 2]) -> [f32; 2] {
+        let r = [point[0] - self.position[0], point[1] - self.position[1]];
+
+        [
+            self.velocity[0] - self.angular_velocity * r[1],
+            self.velocity[1] + self.angular_velocity * r[0],
+        ]
+    }
+
+    /// Advances the body by one step under the force and moment the flow exerted on it during
+    /// the last one and `gravity`, using semi-implicit Euler. Buoyancy is part of the force
+    /// if the fluid feels the same gravity.
+    pub fn advance(&mut self, force: &ObstacleForce, gravity: [f32; 2]) {
+        let total = [
+            force.force[0] + self.mass * gravity[0],
+            force.force[1] + self.mass * gravity[1],
+        ];
+
+        match self.pivot {
+            None => {
+                self.velocity[0] += total[0] / self.mass;
+                self.velocity[1] += total[1] / self.mass;
+                self.position[0] += self.velocity[0];
+                self.position[1] += self.velocity[1];
+                self.angular_velocity += force.moment / self.inertia;
+                self.angle += self.angular_velocity;
+            }
+            Some(pivot) => {
+                let r = [self.position[0] - pivot[0], self.position[1] - pivot[1]];
+                let moment = force.moment + r[0] * total[1] - r[1] * total[0];
+                let inertia = self.inertia + self.mass * (r[0] * r[0] + r[1] * r[1]);
+
+                self.angular_velocity += moment / inertia;
+                self.angle += self.angular_velocity;
+
+                let (sin, cos) = self.angular_velocity.sin_cos();
+                let r = [cos * r[0] - sin * r[1], sin * r[0] + cos * r[1]];
+
+                self.position = [pivot[0] + r[0], pivot[1] + r[1]];
+                self.velocity = [-self.angular_velocity * r[1], self.angular_velocity * r[0]];
+            }
+        }
+    }
+
+    /// The body as laid out in the body buffer of the shader.
+    pub(crate) fn pack(&self) -> [[f32; 4]; BODY_STRIDE] {
+        let mut values = [[0.0; 4]; BODY_STRIDE];
+
+        values[0] = [self.position[0], self.position[1], self.angle, 0.0];
+        values[2] = [
+            self.velocity[0],
+            self.velocity[1],
+            self.angular_velocity,
+            self.obstacle as f32,
+        ];
+
+        match &self.outline {
+            Outline::Circle { radius } => values[1] = [*radius, 0.0, 0.0, 0.0],
+            Outline::Polygon { points } => {
+                values[1] = [0.0, points.len() as f32, 0.0, 0.0];
+
+                for (i, point) in points.iter().take(MAX_BODY_VERTICES).enumerate() {
+                    values[3 + i / 2][2 * (i % 2)] = point[0];
+                    values[3 + i / 2][2 * (i % 2) + 1] = point[1];
+                }
+            }
+        }
+
+        values
+    }
+}
@@ -0,0 +1,300 @@
+//! Per-edge boundary conditions of the lattice.
+
+use serde::Deserialize;
+
+#[derive(Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Edge {
+    /// `x == 0`
+    Left,
+    /// `x == width - 1`
+    Right,
+    /// `y == 0`
+    Top,
+    /// `y == height - 1`
+    Bottom,
+}
+
+impl Edge {
+    /// All edges, in the order the compute shader indexes them.
+    pub const ALL: [Edge; 4] = [Edge::Left, Edge::Right, Edge::Top, Edge::Bottom];
+
+    /// Unit normal pointing into the domain.
+    pub fn normal(self) -> [i32; 2] {
+        match self {
+            Edge::Left => [1, 0],
+            Edge::Right => [-1, 0],
+            Edge::Top => [0, 1],
+            Edge::Bottom => [0, -1],
+        }
+    }
+
+    pub fn contains(self, x: u32, y: u32, size: [u32; 2]) -> bool {
+        match self {
+            Edge::Left => x == 0,
+            Edge::Right => x == size[0] - 1,
+            Edge::Top => y == 0,
+            Edge::Bottom => y == size[1] - 1,
+        }
+    }
+}
+
+#[derive(Deserialize, Clone, Copy, Debug, Default, PartialEq)]
+#[serde(tag = "type", rename_all = "kebab-case")]
+pub enum Boundary {
+    /// Populations leaving through the edge enter on the opposite one.
+    #[default]
+    Periodic,
+    /// Zou–He velocity inlet, the density follows from the known populations.
+    Velocity { velocity: [f32; 2] },
+    /// Zou–He constant density outlet, the velocity is normal to the edge.
+    Pressure { density: f32 },
+    /// Halfway bounce-back at the edge.
+    NoSlip,
+    /// Specular reflection at the edge.
+    FreeSlip,
+    /// Zero-gradient outflow.
+    Open,
+    /// Halfway bounce-back at a wall sliding along the edge with `velocity`, e.g. the lid of a
+    /// cavity. The wall stays in place, so `velocity` should be parallel to the edge.
+    MovingWall { velocity: [f32; 2] },
+}
+
+impl Boundary {
+    /// Boundary type constant used by the compute shader.
+    pub fn kind(&self) -> u32 {
+        match self {
+            Boundary::Periodic => 0,
+            Boundary::Velocity { .. } => 1,
+            Boundary::Pressure { .. } => 2,
+            Boundary::NoSlip => 3,
+            Boundary::FreeSlip => 4,
+            Boundary::Open => 5,
+            Boundary::MovingWall { .. } => 6,
+        }
+    }
+
+    /// Prescribed value as passed to the compute shader.
+    pub fn value(&self) -> [f32; 4] {
+        match *self {
+            Boundary::Velocity { velocity } | Boundary::MovingWall { velocity } => {
+                [velocity[0], velocity[1], 0.0, 0.0]
+            }
+            Boundary::Pressure { density } => [density, 0.0, 0.0, 0.0],
+            _ => [0.0; 4],
+        }
+    }
+
+    /// Applies `convert` to a prescribed velocity, e.g. to convert it to lattice units.
+    pub fn map_velocity(self, convert: impl Fn([f32; 2]) -> [f32; 2]) -> Self {
+        match self {
+            Boundary::Velocity { velocity } => Boundary::Velocity {
+                velocity: convert(velocity),
+            },
+            Boundary::MovingWall { velocity } => Boundary::MovingWall {
+                velocity: convert(velocity),
+            },
+            boundary => boundary,
+        }
+    }
+
+    /// Whether populations leaving through this edge are bounced back, as far as a free-slip
+    /// edge doesn't reflect them.
+    pub fn bounces_back(&self) -> bool {
+        matches!(
+            self,
+            Boundary::NoSlip | Boundary::FreeSlip | Boundary::MovingWall { .. }
+        )
+    }
+
+    /// Whether cells on this edge have to reconstruct the populations entering the domain.
+    pub fn reconstructs(&self) -> bool {
+        matches!(
+            self,
+            Boundary::Velocity { .. } | Boundary::Pressure { .. } | Boundary::Open
+        )
+    }
+}
+
+#[derive(Deserialize, Clone, Copy, Debug, Default, PartialEq)]
+#[serde(default)]
+pub struct Boundaries {
+    pub left: Boundary,
+    pub right: Boundary,
+    pub top: Boundary,
+    pub bottom: Boundary,
+}
+
+impl Boundaries {
+    /// Inflow with `velocity` on the left, outflow at `density` on the right and no-slip
+    /// walls at the top and bottom.
+    pub fn channel(velocity: [f32; 2], density: f32) -> Self {
+        Self {
+            left: Boundary::Velocity { velocity },
+            right: Boundary::Pressure { density },
+            top: Boundary::NoSlip,
+            bottom: Boundary::NoSlip,
+        }
+    }
+
+    pub fn get(&self, edge: Edge) -> Boundary {
+        match edge {
+            Edge::Left => self.left,
+            Edge::Right => self.right,
+            Edge::Top => self.top,
+            Edge::Bottom => self.bottom,
+        }
+    }
+
+    pub fn kinds(&self) -> [u32; 4] {
+        let mut kinds = [0; 4];
+
+        for (kind, &edge) in kinds.iter_mut().zip(&Edge::ALL) {
+            *kind = self.get(edge).kind();
+        }
+
+        kinds
+    }
+
+    pub fn values(&self) -> [[f32; 4]; 4] {
+        let mut values = [[0.0; 4]; 4];
+
+        for (value, &edge) in values.iter_mut().zip(&Edge::ALL) {
+            *value = self.get(edge).value();
+        }
+
+        values
+    }
+
+    /// Applies `convert` to the prescribed velocities, e.g. to convert them to lattice units.
+    pub fn map_velocities(self, convert: impl Fn([f32; 2]) -> [f32; 2]) -> Self {
+        Self {
+            left: self.left.map_velocity(&convert),
+            right: self.right.map_velocity(&convert),
+            top: self.top.map_velocity(&convert),
+            bottom: self.bottom.map_velocity(&convert),
+        }
+    }
+}
+
+/// Boundaries of a 3D lattice. The edges of the 2D lattice become the faces normal to `x` and
+/// `y`, prescribed velocities on all faces stay in the `x`-`y` plane.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Boundaries3d {
+    pub sides: Boundaries,
+    /// `z == 0`
+    pub front: Boundary,
+    /// `z == depth - 1`
+    pub back: Boundary,
+}
+
+impl Boundaries3d {
+    /// All faces, in the order the 3D compute shader indexes them: left, right, top, bottom,
+    /// front and back.
+    pub fn faces(&self) -> [Boundary; 6] {
+        let sides = &self.sides;
+
+        [
+            sides.left,
+            sides.right,
+            sides.top,
+            sides.bottom,
+            self.front,
+            self.back,
+        ]
+    }
+
+    /// Boundary type constants of all faces, packed into two vectors for the compute shader.
+    pub fn kinds(&self) -> [[u32; 4]; 2] {
+        let mut kinds = [[0; 4]; 2];
+
+        for (face, boundary) in self.faces().iter().enumerate() {
+            kinds[face / 4][face % 4] = boundary.kind();
+        }
+
+        kinds
+    }
+
+    pub fn values(&self) -> [[f32; 4]; 6] {
+        let mut values = [[0.0; 4]; 6];
+
+        for (value, boundary) in values.iter_mut().zip(&self.faces()) {
+            *value = boundary.value();
+        }
+
+        values
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn edges_parse_and_default_to_periodic() {
+        let boundaries: Boundaries = toml::from_str(
+            r#"
+            left = { type = "velocity", velocity = [0.1, 0.0] }
+            right = { type = "pressure", density = 1.0 }
+            top = { type = "moving-wall", velocity = [0.05, 0.0] }
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(boundaries.bottom, Boundary::Periodic);
+        assert_eq!(boundaries.kinds(), [1, 2, 6, 0]);
+        assert_eq!(
+            boundaries.values(),
+            [
+                [0.1, 0.0, 0.0, 0.0],
+                [1.0, 0.0, 0.0, 0.0],
+                [0.05, 0.0, 0.0, 0.0],
+                [0.0; 4],
+            ]
+        );
+    }
+
+    #[test]
+    fn only_prescribed_velocities_are_mapped() {
+        let boundaries = Boundaries {
+            top: Boundary::MovingWall {
+                velocity: [1.0, 0.0],
+            },
+            ..Boundaries::channel([2.0, 0.0], 1.5)
+        };
+
+        let mapped = boundaries.map_velocities(|[u, v]| [0.5 * u, 0.5 * v]);
+
+        assert_eq!(
+            mapped.left,
+            Boundary::Velocity {
+                velocity: [1.0, 0.0]
+            }
+        );
+        assert_eq!(mapped.right, Boundary::Pressure { density: 1.5 });
+        assert_eq!(
+            mapped.top,
+            Boundary::MovingWall {
+                velocity: [0.5, 0.0]
+            }
+        );
+        assert_eq!(mapped.bottom, Boundary::NoSlip);
+    }
+
+    #[test]
+    fn edges_contain_their_cells() {
+        let size = [4, 3];
+
+        assert!(Edge::Left.contains(0, 1, size));
+        assert!(Edge::Right.contains(3, 1, size));
+        assert!(Edge::Top.contains(2, 0, size));
+        assert!(Edge::Bottom.contains(2, 2, size));
+        assert!(!Edge::Right.contains(2, 1, size));
+
+        for edge in Edge::ALL {
+            let [x, y] = edge.normal();
+
+            assert_eq!(x.abs() + y.abs(), 1);
+        }
+    }
+}
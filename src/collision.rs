@@ -0,0 +1,542 @@
+//! Collision operators of the compute kernel and their CPU counterparts.
+//!
+//! All operators relax the shear modes with the rate `2 * beta`, so the viscosity only depends
+//! on `beta`. They differ in the equilibrium and in how the remaining modes are treated. The
+//! entropic operator replaces the 2 by a per-cell `alpha` that keeps the entropy from
+//! decreasing.
+
+use serde::Deserialize;
+
+use crate::lattice::{self, C, OPP, Q, W};
+
+#[derive(Deserialize, Clone, Copy, Debug, Default, PartialEq)]
+#[serde(tag = "type", rename_all = "kebab-case")]
+pub enum Collision {
+    /// Entropic LBM: relaxes towards the product-form entropic equilibrium with the rate
+    /// `alpha * beta`, `alpha` being solved per cell from the entropy condition.
+    #[default]
+    Entropic,
+    /// Single relaxation time towards the second order polynomial equilibrium.
+    Bgk,
+    /// Two relaxation times, the antisymmetric rate follows from the magic parameter.
+    Trt {
+        #[serde(default = "default_magic")]
+        magic: f32,
+    },
+    /// Multiple relaxation times in the moment space of Lallemand & Luo (2000).
+    Mrt {
+        #[serde(default = "default_energy_rate")]
+        energy: f32,
+        #[serde(default = "default_energy_square_rate")]
+        energy_square: f32,
+        #[serde(default = "default_heat_flux_rate")]
+        heat_flux: f32,
+    },
+    /// BGK on the non-equilibrium part projected onto the second order Hermite moments.
+    Regularized,
+}
+
+fn default_magic() -> f32 {
+    0.25
+}
+
+fn default_energy_rate() -> f32 {
+    1.64
+}
+
+fn default_energy_square_rate() -> f32 {
+    1.54
+}
+
+fn default_heat_flux_rate() -> f32 {
+    1.9
+}
+
+/// Norms of the rows of the MRT transformation matrix.
+const MOMENT_NORM: [f32; Q] = [9.0, 36.0, 36.0, 6.0, 12.0, 6.0, 12.0, 4.0, 4.0];
+
+impl Collision {
+    /// Collision type constant used by the compute shader.
+    pub fn kind(&self) -> u32 {
+        match self {
+            Collision::Entropic => 0,
+            Collision::Bgk => 1,
+            Collision::Trt { .. } => 2,
+            Collision::Mrt { .. } => 3,
+            Collision::Regularized => 4,
+        }
+    }
+
+    /// Operator parameters as passed to the compute shader.
+    pub fn values(&self) -> [f32; 4] {
+        match *self {
+            Collision::Trt { magic } => [magic, 0.0, 0.0, 0.0],
+            Collision::Mrt {
+                energy,
+                energy_square,
+                heat_flux,
+            } => [energy, energy_square, heat_flux, 0.0],
+            _ => [0.0; 4],
+        }
+    }
+
+    /// Equilibrium population `i` the operator relaxes towards.
+    pub fn f_eq(&self, i: usize, rho: f32, p: [f32; 2]) -> f32 {
+        match self {
+            Collision::Entropic => lattice::f_eq(i, rho, p),
+            _ => lattice::f_eq_polynomial(i, rho, p),
+        }
+    }
+
+    /// Relaxes `f` towards the equilibrium of `rho` and `p` and applies `force` with the Guo
+    /// scheme, see `collide` in the shader. `p` already includes half the force. Populations
+    /// aren't clamped, only the entropic operator keeps them non-negative.
+    pub fn collide(&self, beta: f32, f: &mut [f32; Q], rho: f32, p: [f32; 2], force: [f32; 2]) {
+        let omega = 2.0 * beta;
+
+        let u = [p[0] / rho, p[1] / rho];
+
+        let mut source = [0.0; Q];
+
+        for (i, source) in source.iter_mut().enumerate() {
+            *source = guo_source(i, u, force);
+        }
+
+        match *self {
+            Collision::Entropic => {
+                let mut delta = [0.0; Q];
+                let mut delta_unforced = [0.0; Q];
+
+                let p_unforced = [p[0] - 0.5 * force[0], p[1] - 0.5 * force[1]];
+
+                for i in 0..Q {
+                    delta[i] = lattice::f_eq(i, rho, p) - f[i];
+                    delta_unforced[i] = lattice::f_eq(i, rho, p_unforced) - f[i];
+                }
+
+                // The entropy condition is solved without the force, whose momentum would
+                // otherwise count as a departure from equilibrium.
+                let alpha = entropic_alpha(f, &delta_unforced);
+
+                // The source can push populations negative, where the entropy of the next step
+                // is undefined. alpha is limited to keep them non-negative. Cells where no alpha
+                // does skip the force for this step and take the unforced step, which stays
+                // between f and the non-negative f + alpha_max * delta of `entropic_alpha`.
+                match limit_forced_alpha(f, &delta, &source, beta, alpha) {
+                    Some(alpha) => {
+                        for i in 0..Q {
+                            f[i] +=
+                                alpha * beta * delta[i] + (1.0 - 0.5 * alpha * beta) * source[i];
+                        }
+                    }
+                    None => {
+                        for i in 0..Q {
+                            f[i] += alpha * beta * delta_unforced[i];
+                        }
+                    }
+                }
+            }
+            Collision::Bgk => {
+                for (i, f) in f.iter_mut().enumerate() {
+                    *f += omega * (lattice::f_eq_polynomial(i, rho, p) - *f)
+                        + (1.0 - 0.5 * omega) * source[i];
+                }
+            }
+            Collision::Trt { magic } => {
+                let omega_minus = 1.0 / (magic / (1.0 / omega - 0.5) + 0.5);
+
+                let f_in = *f;
+
+                for i in 0..Q {
+                    let f_eq = lattice::f_eq_polynomial(i, rho, p);
+                    let f_eq_opp = lattice::f_eq_polynomial(OPP[i], rho, p);
+
+                    let plus = 0.5 * (f_in[i] + f_in[OPP[i]]) - 0.5 * (f_eq + f_eq_opp);
+                    let minus = 0.5 * (f_in[i] - f_in[OPP[i]]) - 0.5 * (f_eq - f_eq_opp);
+
+                    let source_plus = 0.5 * (source[i] + source[OPP[i]]);
+                    let source_minus = 0.5 * (source[i] - source[OPP[i]]);
+
+                    f[i] = f_in[i] - omega * plus - omega_minus * minus
+                        + (1.0 - 0.5 * omega) * source_plus
+                        + (1.0 - 0.5 * omega_minus) * source_minus;
+                }
+            }
+            Collision::Mrt {
+                energy,
+                energy_square,
+                heat_flux,
+            } => {
+                let uu = u[0] * u[0] + u[1] * u[1];
+
+                let m_eq = [
+                    rho,
+                    -2.0 * rho + 3.0 * rho * uu,
+                    rho - 3.0 * rho * uu,
+                    p[0],
+                    -p[0],
+                    p[1],
+                    -p[1],
+                    rho * (u[0] * u[0] - u[1] * u[1]),
+                    rho * u[0] * u[1],
+                ];
+
+                let rates = [
+                    omega,
+                    energy,
+                    energy_square,
+                    omega,
+                    heat_flux,
+                    omega,
+                    heat_flux,
+                    omega,
+                    omega,
+                ];
+
+                let mut dm = [0.0; Q];
+
+                // The forcing term is transformed to moment space as well, each moment of it is
+                // scaled with its own (1 - s / 2).
+                for k in 0..Q {
+                    let m: f32 = (0..Q).map(|i| moment_basis(k, C[i]) * f[i]).sum();
+                    let m_source: f32 = (0..Q).map(|i| moment_basis(k, C[i]) * source[i]).sum();
+
+                    dm[k] = (rates[k] * (m - m_eq[k]) - (1.0 - 0.5 * rates[k]) * m_source)
+                        / MOMENT_NORM[k];
+                }
+
+                for (i, f) in f.iter_mut().enumerate() {
+                    *f -= (0..Q).map(|k| moment_basis(k, C[i]) * dm[k]).sum::<f32>();
+                }
+            }
+            Collision::Regularized => {
+                let mut f_eq = [0.0; Q];
+                let mut j = [0.0; 2];
+                let mut pi = [0.0; 3];
+
+                for i in 0..Q {
+                    f_eq[i] = lattice::f_eq_polynomial(i, rho, p);
+
+                    let f_neq = f[i] - f_eq[i];
+                    let c = [C[i][0] as f32, C[i][1] as f32];
+
+                    j[0] += f_neq * c[0];
+                    j[1] += f_neq * c[1];
+
+                    pi[0] += f_neq * c[0] * c[0];
+                    pi[1] += f_neq * c[0] * c[1];
+                    pi[2] += f_neq * c[1] * c[1];
+                }
+
+                for i in 0..Q {
+                    let c = [C[i][0] as f32, C[i][1] as f32];
+
+                    let contraction = (c[0] * c[0] - 1.0 / 3.0) * pi[0]
+                        + 2.0 * c[0] * c[1] * pi[1]
+                        + (c[1] * c[1] - 1.0 / 3.0) * pi[2];
+
+                    // The first order part carries the half force of the velocity shift.
+                    let first_order = 3.0 * (c[0] * j[0] + c[1] * j[1]);
+
+                    f[i] = f_eq[i]
+                        + (1.0 - omega) * W[i] * (first_order + 4.5 * contraction)
+                        + (1.0 - 0.5 * omega) * source[i];
+                }
+            }
+        }
+    }
+}
+
+/// Forcing term of the Guo scheme before the `(1 - omega / 2)` factor.
+fn guo_source(i: usize, u: [f32; 2], force: [f32; 2]) -> f32 {
+    let c = [C[i][0] as f32, C[i][1] as f32];
+
+    let cu = c[0] * u[0] + c[1] * u[1];
+    let cf = c[0] * force[0] + c[1] * force[1];
+
+    W[i] * (3.0 * ((c[0] - u[0]) * force[0] + (c[1] - u[1]) * force[1]) + 9.0 * cu * cf)
+}
+
+const ALPHA_ITERATIONS: usize = 20;
+const ALPHA_TOLERANCE: f32 = 1e-5;
+
+/// Below this relative distance from the equilibrium the entropy difference is lost to
+/// rounding and `alpha = 2` is exact to leading order.
+const ALPHA_MIN_DEVIATION: f32 = 1e-3;
+
+/// `(1 + x) ln(1 + x) - x`, expanded for small `x` where the logarithm loses precision.
+fn entropy_increment(x: f32) -> f32 {
+    if x.abs() < 1e-2 {
+        x * x * (0.5 - x / 6.0 + x * x / 12.0)
+    } else {
+        (1.0 + x) * (1.0 + x).ln() - x
+    }
+}
+
+/// Solves `H(f + alpha * delta) = H(f)` for the non-trivial root, see `entropic_alpha` in the
+/// shader. Falls back to the largest `alpha` up to 2 that keeps all populations positive.
+pub fn entropic_alpha(f: &[f32; Q], delta: &[f32; Q]) -> f32 {
+    let mut alpha_max = f32::INFINITY;
+    let mut deviation = 0.0f32;
+    let mut positive = true;
+
+    for i in 0..Q {
+        if delta[i] < 0.0 {
+            alpha_max = alpha_max.min(-f[i] / delta[i]);
+        }
+
+        if f[i] > 0.0 {
+            deviation = deviation.max(delta[i].abs() / f[i]);
+        } else {
+            positive = false;
+        }
+    }
+
+    let fallback = alpha_max.min(2.0);
+
+    if !positive || deviation < ALPHA_MIN_DEVIATION {
+        return fallback;
+    }
+
+    // The mass of delta vanishes, dropping it from the sums keeps its rounding error from
+    // swamping the quadratic entropy difference.
+    let rho: f32 = f.iter().sum();
+
+    let linear: f32 = (0..Q).map(|i| delta[i] * (f[i] / (W[i] * rho)).ln()).sum();
+
+    let mut alpha = 2.0;
+
+    for _ in 0..ALPHA_ITERATIONS {
+        if !(alpha > 1.0 && alpha < alpha_max) {
+            break;
+        }
+
+        let mut g = alpha * linear;
+        let mut dg = 0.0;
+
+        for i in 0..Q {
+            g += f[i] * entropy_increment(alpha * delta[i] / f[i]);
+            dg += delta[i] * ((f[i] + alpha * delta[i]) / (W[i] * rho)).ln();
+        }
+
+        let step = g / dg;
+        alpha -= step;
+
+        if step.abs() < ALPHA_TOLERANCE * alpha {
+            return if alpha > 1.0 && alpha < alpha_max {
+                alpha
+            } else {
+                fallback
+            };
+        }
+    }
+
+    fallback
+}
+
+/// Limits `alpha` to the range in which the forced entropic step keeps all populations
+/// non-negative, see `limit_forced_alpha` in the shader. `None` if there is no such `alpha`.
+fn limit_forced_alpha(
+    f: &[f32; Q],
+    delta: &[f32; Q],
+    source: &[f32; Q],
+    beta: f32,
+    alpha: f32,
+) -> Option<f32> {
+    let mut lower = 0.0f32;
+    let mut upper = f32::INFINITY;
+
+    for i in 0..Q {
+        let offset = f[i] + source[i];
+        let slope = beta * (delta[i] - 0.5 * source[i]);
+
+        if slope < 0.0 {
+            upper = upper.min(-offset / slope);
+        } else if slope > 0.0 {
+            lower = lower.max(-offset / slope);
+        } else if offset < 0.0 {
+            return None;
+        }
+    }
+
+    if lower > upper {
+        return None;
+    }
+
+    Some(alpha.max(lower).min(upper))
+}
+
+/// Entry `k, i` of the MRT transformation matrix, with the velocity `c` of direction `i`.
+fn moment_basis(k: usize, c: [i32; 2]) -> f32 {
+    let [cx, cy] = [c[0] as f32, c[1] as f32];
+    let c2 = cx * cx + cy * cy;
+
+    match k {
+        0 => 1.0,
+        1 => -4.0 + 3.0 * c2,
+        2 => 4.0 - 10.5 * c2 + 4.5 * c2 * c2,
+        3 => cx,
+        4 => (-5.0 + 3.0 * c2) * cx,
+        5 => cy,
+        6 => (-5.0 + 3.0 * c2) * cy,
+        7 => cx * cx - cy * cy,
+        _ => cx * cy,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const OPERATORS: [Collision; 5] = [
+        Collision::Entropic,
+        Collision::Bgk,
+        Collision::Trt { magic: 0.25 },
+        Collision::Mrt {
+            energy: 1.64,
+            energy_square: 1.54,
+            heat_flux: 1.9,
+        },
+        Collision::Regularized,
+    ];
+
+    fn equilibrium(rho: f32, u: [f32; 2]) -> [f32; Q] {
+        let mut f = [0.0; Q];
+
+        for (i, f) in f.iter_mut().enumerate() {
+            *f = lattice::f_eq(i, rho, [rho * u[0], rho * u[1]]);
+        }
+
+        f
+    }
+
+    fn moments(f: &[f32; Q]) -> (f32, [f32; 2]) {
+        let mut rho = 0.0;
+        let mut p = [0.0; 2];
+
+        for i in 0..Q {
+            rho += f[i];
+            p[0] += C[i][0] as f32 * f[i];
+            p[1] += C[i][1] as f32 * f[i];
+        }
+
+        (rho, p)
+    }
+
+    #[test]
+    fn equilibrium_is_kept_by_every_operator() {
+        for collision in OPERATORS {
+            let mut f = equilibrium(1.0, [0.05, -0.02]);
+            let expected = f;
+            let (rho, p) = moments(&f);
+
+            collision.collide(0.9, &mut f, rho, p, [0.0; 2]);
+
+            for i in 0..Q {
+                assert!(
+                    (f[i] - expected[i]).abs() < 1e-4,
+                    "{:?} moved population {} from {} to {}",
+                    collision,
+                    i,
+                    expected[i],
+                    f[i]
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn every_operator_conserves_mass_and_momentum() {
+        // Fast enough for the polynomial equilibrium of the rest population to turn negative.
+        let mut f_in = equilibrium(1.0, [0.85, 0.0]);
+        f_in[2] *= 1.2;
+        f_in[7] *= 0.8;
+
+        for collision in OPERATORS {
+            let mut f = f_in;
+            let (rho, p) = moments(&f);
+
+            collision.collide(0.99, &mut f, rho, p, [0.0; 2]);
+
+            let (rho_out, p_out) = moments(&f);
+
+            assert!(
+                (rho_out - rho).abs() < 1e-5,
+                "{:?} changed the mass",
+                collision
+            );
+            assert!(
+                (p_out[0] - p[0]).abs() < 1e-5 && (p_out[1] - p[1]).abs() < 1e-5,
+                "{:?} changed the momentum",
+                collision
+            );
+        }
+    }
+
+    #[test]
+    fn entropic_alpha_is_two_close_to_equilibrium() {
+        let f = equilibrium(1.0, [0.05, 0.0]);
+        let target = equilibrium(1.0, [0.06, 0.0]);
+
+        let mut delta = [0.0; Q];
+
+        for i in 0..Q {
+            delta[i] = target[i] - f[i];
+        }
+
+        assert!((entropic_alpha(&f, &delta) - 2.0).abs() < 1e-2);
+    }
+
+    #[test]
+    fn forced_entropic_step_keeps_populations_non_negative() {
+        for &force in &[[0.05, 0.0], [-0.2, 0.1], [0.4, -0.4]] {
+            let mut f = equilibrium(1.0, [0.3, 0.0]);
+            f[3] = 1e-4;
+
+            let (rho, p) = moments(&f);
+            let p = [p[0] + 0.5 * force[0], p[1] + 0.5 * force[1]];
+
+            Collision::Entropic.collide(0.99, &mut f, rho, p, force);
+
+            assert!(f.iter().all(|f| *f >= 0.0), "{:?} gave {:?}", force, f);
+            assert!((moments(&f).0 - rho).abs() < 1e-5);
+        }
+    }
+
+    #[test]
+    fn entropic_step_without_a_forced_alpha_skips_the_force() {
+        let force = [0.4, -0.4];
+
+        let mut f = equilibrium(1.0, [0.3, 0.0]);
+        f[3] = 1e-4;
+
+        let (rho, p) = moments(&f);
+        let p_forced = [p[0] + 0.5 * force[0], p[1] + 0.5 * force[1]];
+
+        let mut delta = [0.0; Q];
+        let mut delta_unforced = [0.0; Q];
+        let mut source = [0.0; Q];
+
+        for i in 0..Q {
+            delta[i] = lattice::f_eq(i, rho, p_forced) - f[i];
+            delta_unforced[i] = lattice::f_eq(i, rho, p) - f[i];
+            source[i] = guo_source(i, [p_forced[0] / rho, p_forced[1] / rho], force);
+        }
+
+        let alpha = entropic_alpha(&f, &delta_unforced);
+
+        assert_eq!(limit_forced_alpha(&f, &delta, &source, 0.99, alpha), None);
+
+        let mut forced = f;
+        Collision::Entropic.collide(0.99, &mut forced, rho, p_forced, force);
+
+        let mut unforced = f;
+        Collision::Entropic.collide(0.99, &mut unforced, rho, p, [0.0; 2]);
+
+        for i in 0..Q {
+            assert!((forced[i] - unforced[i]).abs() < 1e-6);
+        }
+
+        assert!(forced.iter().all(|f| *f >= 0.0));
+    }
+}
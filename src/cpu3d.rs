@@ -0,0 +1,465 @@
+//! A pure Rust implementation of the collide-and-stream step in `main3d.comp`.
+//!
+//! Mirrors the 3D kernel operation by operation, like [`crate::cpu`] does for the 2D one.
+//! Cells that no fluid streams into keep whatever the output lattice held two steps ago.
+
+use crate::boundary::Boundary;
+use crate::fields3d::InitialConditions3d;
+use crate::lattice::CellType;
+use crate::lattice3d::{self, VelocitySet, C, FACE_NORMALS, Q_MAX};
+use crate::parameters::Parameters3d;
+
+pub struct CpuSimulation3d {
+    size: [u32; 3],
+    velocity_set: VelocitySet,
+    lattice: [Vec<f32>; 2],
+    type_mask: Vec<u8>,
+    cell_data: Vec<[f32; 4]>,
+    current: usize,
+    steps: u64,
+    parameters: Parameters3d,
+    init: bool,
+    initial_conditions: Option<InitialConditions3d>,
+}
+
+impl CpuSimulation3d {
+    pub fn new(size: [u32; 3], velocity_set: VelocitySet) -> Self {
+        let cells = (size[0] * size[1] * size[2]) as usize;
+        let q = velocity_set.q();
+
+        Self {
+            size,
+            velocity_set,
+            lattice: [vec![0.0; cells * q], vec![0.0; cells * q]],
+            type_mask: vec![CellType::Fluid as u8; cells],
+            cell_data: vec![[1.0, 0.0, 0.0, 0.0]; cells],
+            current: 0,
+            steps: 0,
+            parameters: Parameters3d::default(),
+            init: true,
+            initial_conditions: None,
+        }
+    }
+
+    pub fn step(&mut self) {
+        let [width, height, depth] = [
+            self.size[0] as i32,
+            self.size[1] as i32,
+            self.size[2] as i32,
+        ];
+
+        if self.init {
+            let initial_conditions = self
+                .initial_conditions
+                .clone()
+                .unwrap_or_else(|| InitialConditions3d::new(self.size));
+
+            self.lattice[self.current] = initial_conditions.distributions(self.velocity_set);
+            self.type_mask = initial_conditions.type_mask.clone();
+            self.cell_data = initial_conditions.cell_data();
+            self.init = false;
+        }
+
+        let input = std::mem::take(&mut self.lattice[self.current]);
+        let mut output = std::mem::take(&mut self.lattice[1 - self.current]);
+
+        for z in 0..depth {
+            for y in 0..height {
+                for x in 0..width {
+                    let cell_type = self.type_mask[self.cell(x, y, z)];
+
+                    if cell_type == CellType::Fluid as u8 {
+                        self.collide_and_stream(x, y, z, &input, &mut output);
+                    } else if cell_type == CellType::Sink as u8
+                        || cell_type == CellType::Source as u8
+                    {
+                        self.emit(x, y, z, &mut output);
+                    }
+                }
+            }
+        }
+
+        self.lattice[self.current] = input;
+        self.lattice[1 - self.current] = output;
+
+        self.current = 1 - self.current;
+        self.steps += 1;
+    }
+
+    pub fn reset(&mut self) {
+        self.init = true;
+        self.steps = 0;
+    }
+
+    pub fn steps(&self) -> u64 {
+        self.steps
+    }
+
+    /// Replaces the domain at rest the simulation starts with and resets it.
+    pub fn set_initial_conditions(&mut self, initial_conditions: InitialConditions3d) {
+        assert_eq!(initial_conditions.size, self.size);
+
+        self.initial_conditions = Some(initial_conditions);
+        self.reset();
+    }
+
+    pub fn size(&self) -> [u32; 3] {
+        self.size
+    }
+
+    pub fn velocity_set(&self) -> VelocitySet {
+        self.velocity_set
+    }
+
+    pub fn parameters(&self) -> Parameters3d {
+        self.parameters
+    }
+
+    pub fn set_parameters(&mut self, parameters: Parameters3d) {
+        self.parameters = parameters;
+    }
+
+    /// The distributions written by the most recent step, stored as `[i][z][y][x]`.
+    ///
+    /// This is the same layout the GPU images have when copied into a buffer.
+    pub fn distributions(&self) -> &[f32] {
+        &self.lattice[self.current]
+    }
+
+    pub fn type_mask(&self) -> &[u8] {
+        &self.type_mask
+    }
+
+    fn collide_and_stream(&self, x: i32, y: i32, z: i32, input: &[f32], output: &mut [f32]) {
+        let velocity_set = self.velocity_set;
+        let q = velocity_set.q();
+
+        let mut f = [0.0; Q_MAX];
+
+        for (i, f) in f.iter_mut().enumerate().take(q) {
+            *f = input[self.index(x, y, z, i)];
+        }
+
+        self.apply_faces(x, y, z, input, &mut f);
+
+        let mut rho = 0.0;
+        let mut p = [0.0; 3];
+
+        for i in 0..q {
+            rho += f[i];
+
+            for d in 0..3 {
+                p[d] += C[i][d] as f32 * f[i];
+            }
+        }
+
+        let mut force = [0.0; 3];
+
+        for d in 0..3 {
+            force[d] = rho * self.parameters.gravity[d] + self.parameters.force[d];
+
+            // Guo scheme: the equilibrium velocity includes half the force.
+            p[d] += 0.5 * force[d];
+        }
+
+        let omega = 2.0 * self.parameters.beta;
+        let u = [p[0] / rho, p[1] / rho, p[2] / rho];
+
+        for (i, f) in f.iter_mut().enumerate().take(q) {
+            let source = guo_source(velocity_set, i, u, force);
+
+            *f += omega * (velocity_set.f_eq(i, rho, p) - *f) + (1.0 - 0.5 * omega) * source;
+        }
+
+        self.stream(x, y, z, rho, &f, output);
+    }
+
+    /// Streams the populations of a fluid cell, see `stream` in the shader.
+    fn stream(&self, x: i32, y: i32, z: i32, rho: f32, f: &[f32; Q_MAX], output: &mut [f32]) {
+        for (i, &f_next) in f.iter().enumerate().take(self.velocity_set.q()) {
+            let (crossed, face, (nx, ny, nz)) = self.neighbour(x, y, z, C[i]);
+
+            if crossed > 0 {
+                self.leave_domain(x, y, z, i, crossed, face, rho, f_next, output);
+                continue;
+            }
+
+            let neighbour_type = self.type_mask[self.cell(nx, ny, nz)];
+
+            if neighbour_type == CellType::Fluid as u8 {
+                output[self.index(nx, ny, nz, i)] = f_next;
+            } else if CellType::is_wall(neighbour_type) {
+                let state = self.cell_data[self.cell(nx, ny, nz)];
+                let u_wall = [state[1], state[2], state[3]];
+
+                output[self.index(x, y, z, lattice3d::opp(i))] =
+                    f_next - self.wall_correction(i, rho, u_wall);
+            }
+
+            // Populations streaming into sink and source cells are absorbed.
+        }
+    }
+
+    /// Sinks and sources emit the equilibrium of their state, see `main` in the shader.
+    fn emit(&self, x: i32, y: i32, z: i32, output: &mut [f32]) {
+        let cell = self.cell(x, y, z);
+        let state = self.cell_data[cell];
+
+        let rho_target = state[0];
+        let p_target = if self.type_mask[cell] == CellType::Source as u8 {
+            [
+                rho_target * state[1],
+                rho_target * state[2],
+                rho_target * state[3],
+            ]
+        } else {
+            [0.0; 3]
+        };
+
+        for i in 0..self.velocity_set.q() {
+            let (crossed, _, (nx, ny, nz)) = self.neighbour(x, y, z, C[i]);
+
+            if crossed == 0 && self.type_mask[self.cell(nx, ny, nz)] == CellType::Fluid as u8 {
+                output[self.index(nx, ny, nz, i)] = self.velocity_set.f_eq(i, rho_target, p_target);
+            }
+        }
+    }
+
+    /// Neighbour in direction `c`, wrapped around periodic faces. Also returns the number of
+    /// non-periodic faces the neighbour lies behind and the last of them.
+    fn neighbour(&self, x: i32, y: i32, z: i32, c: [i32; 3]) -> (u32, usize, (i32, i32, i32)) {
+        let size = [
+            self.size[0] as i32,
+            self.size[1] as i32,
+            self.size[2] as i32,
+        ];
+        let faces = self.parameters.boundaries.faces();
+
+        let next = [x + c[0], y + c[1], z + c[2]];
+
+        let mut crossed = 0;
+        let mut last = 0;
+
+        for d in 0..3 {
+            for &(outside, face) in &[(next[d] < 0, 2 * d), (next[d] >= size[d], 2 * d + 1)] {
+                if outside && faces[face] != Boundary::Periodic {
+                    crossed += 1;
+                    last = face;
+                }
+            }
+        }
+
+        (
+            crossed,
+            last,
+            (
+                (next[0] + size[0]) % size[0],
+                (next[1] + size[1]) % size[1],
+                (next[2] + size[2]) % size[2],
+            ),
+        )
+    }
+
+    /// Handles a population leaving the domain, see `leave_domain` in the shader.
+    #[allow(clippy::too_many_arguments)]
+    fn leave_domain(
+        &self,
+        x: i32,
+        y: i32,
+        z: i32,
+        i: usize,
+        crossed: u32,
+        face: usize,
+        rho: f32,
+        f_next: f32,
+        output: &mut [f32],
+    ) {
+        let boundary = self.parameters.boundaries.faces()[face];
+
+        if boundary == Boundary::FreeSlip && crossed == 1 {
+            let n = FACE_NORMALS[face];
+            let cn = lattice3d::idot(C[i], n);
+
+            let mut tangential = [0; 3];
+            let mut reflected = [0; 3];
+
+            for d in 0..3 {
+                tangential[d] = C[i][d] - cn * n[d];
+                reflected[d] = tangential[d] - cn * n[d];
+            }
+
+            let (target_crossed, _, (tx, ty, tz)) = self.neighbour(x, y, z, tangential);
+
+            if target_crossed == 0 && self.type_mask[self.cell(tx, ty, tz)] == CellType::Fluid as u8
+            {
+                let j = C.iter().position(|&c| c == reflected).unwrap();
+                output[self.index(tx, ty, tz, j)] = f_next;
+                return;
+            }
+        }
+
+        if boundary.bounces_back() {
+            let u_wall = match boundary {
+                Boundary::MovingWall { velocity } => [velocity[0], velocity[1], 0.0],
+                _ => [0.0; 3],
+            };
+
+            output[self.index(x, y, z, lattice3d::opp(i))] =
+                f_next - self.wall_correction(i, rho, u_wall);
+        }
+    }
+
+    /// See `wall_correction` in the shader.
+    fn wall_correction(&self, i: usize, rho: f32, u_wall: [f32; 3]) -> f32 {
+        6.0 * self.velocity_set.weight(i) * rho * lattice3d::dot(C[i], u_wall)
+    }
+
+    /// See `apply_faces` in the shader.
+    fn apply_faces(&self, x: i32, y: i32, z: i32, input: &[f32], f: &mut [f32; Q_MAX]) {
+        let pos = [x, y, z];
+        let faces = self.parameters.boundaries.faces();
+
+        let mut count = 0;
+        let mut last = 0;
+
+        let mut rho = 1.0;
+        let mut u = [0.0; 3];
+
+        for (face, boundary) in faces.iter().enumerate() {
+            let d = face / 2;
+            let on_face = if face % 2 == 0 {
+                pos[d] == 0
+            } else {
+                pos[d] == self.size[d] as i32 - 1
+            };
+
+            if boundary.reconstructs() && on_face {
+                count += 1;
+                last = face;
+
+                match *boundary {
+                    Boundary::Velocity { velocity } => u = [velocity[0], velocity[1], 0.0],
+                    Boundary::Pressure { density } => rho = density,
+                    _ => {}
+                }
+            }
+        }
+
+        let q = self.velocity_set.q();
+
+        if count == 1 && faces[last] == Boundary::Open {
+            let n = FACE_NORMALS[last];
+
+            for (i, f) in f.iter_mut().enumerate().take(q) {
+                if lattice3d::idot(C[i], n) > 0 {
+                    *f = input[self.index(x + n[0], y + n[1], z + n[2], i)];
+                }
+            }
+        } else if count == 1 {
+            self.zou_he(last, f);
+        } else if count > 1 {
+            let p = [rho * u[0], rho * u[1], rho * u[2]];
+
+            for (i, f) in f.iter_mut().enumerate().take(q) {
+                *f = self.velocity_set.f_eq(i, rho, p);
+            }
+        }
+    }
+
+    /// See `zou_he` in the shader.
+    fn zou_he(&self, face: usize, f: &mut [f32; Q_MAX]) {
+        let velocity_set = self.velocity_set;
+        let q = velocity_set.q();
+
+        let n = FACE_NORMALS[face];
+        let axis = face / 2;
+
+        let mut parallel = 0.0;
+        let mut outgoing = 0.0;
+
+        for i in 0..q {
+            let cn = lattice3d::idot(C[i], n);
+
+            if cn == 0 {
+                parallel += f[i];
+            } else if cn < 0 {
+                outgoing += f[i];
+            }
+        }
+
+        let normal = [n[0] as f32, n[1] as f32, n[2] as f32];
+
+        let (rho, u) = match self.parameters.boundaries.faces()[face] {
+            Boundary::Velocity { velocity } => {
+                let u = [velocity[0], velocity[1], 0.0];
+                let un = u[0] * normal[0] + u[1] * normal[1] + u[2] * normal[2];
+
+                ((parallel + 2.0 * outgoing) / (1.0 - un), u)
+            }
+            Boundary::Pressure { density } => {
+                let un = 1.0 - (parallel + 2.0 * outgoing) / density;
+
+                (density, [un * normal[0], un * normal[1], un * normal[2]])
+            }
+            _ => return,
+        };
+
+        // The transverse momentum of the cell is corrected along both tangents.
+        let mut correction = [0.0; 3];
+
+        for (t, correction) in correction.iter_mut().enumerate() {
+            if t == axis {
+                continue;
+            }
+
+            let mut transverse = 0.0;
+            let mut links = 0.0;
+
+            for i in 0..q {
+                let cn = lattice3d::idot(C[i], n);
+
+                if cn == 0 {
+                    transverse += f[i] * C[i][t] as f32;
+                } else if cn > 0 {
+                    links += (C[i][t] * C[i][t]) as f32;
+                }
+            }
+
+            *correction = (transverse - 2.0 * rho * u[t] / 3.0) / links;
+        }
+
+        for i in 0..q {
+            if lattice3d::idot(C[i], n) > 0 {
+                f[i] = f[lattice3d::opp(i)]
+                    + 6.0 * velocity_set.weight(i) * rho * lattice3d::dot(C[i], u)
+                    - lattice3d::dot(C[i], correction);
+            }
+        }
+    }
+
+    fn cell(&self, x: i32, y: i32, z: i32) -> usize {
+        ((z * self.size[1] as i32 + y) * self.size[0] as i32 + x) as usize
+    }
+
+    fn index(&self, x: i32, y: i32, z: i32, i: usize) -> usize {
+        let cells = (self.size[0] * self.size[1] * self.size[2]) as usize;
+
+        i * cells + self.cell(x, y, z)
+    }
+}
+
+/// Forcing term of the Guo scheme before the `(1 - omega / 2)` factor.
+fn guo_source(velocity_set: VelocitySet, i: usize, u: [f32; 3], force: [f32; 3]) -> f32 {
+    let c = C[i];
+
+    let cu = lattice3d::dot(c, u);
+    let cf = lattice3d::dot(c, force);
+
+    let mut drift = 0.0;
+
+    for d in 0..3 {
+        drift += (c[d] as f32 - u[d]) * force[d];
+    }
+
+    velocity_set.weight(i) * (3.0 * drift + 9.0 * cu * cf)
+}
@@ -0,0 +1,29 @@
+//! Parameters of the dye, a passive scalar advected by the flow on a D2Q5 lattice for
+//! visualization and mixing studies.
+
+use serde::Deserialize;
+
+#[derive(Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(default)]
+pub struct Dye {
+    /// Diffusivity in lattice units, the dye relaxes with `1 / (3 * diffusivity + 0.5)`. Small
+    /// values keep the streaks sharp.
+    pub diffusivity: f32,
+    /// Concentration added per step to cells under the brush while it is dragged.
+    pub brush: f32,
+}
+
+impl Default for Dye {
+    fn default() -> Self {
+        Self {
+            diffusivity: 0.002,
+            brush: 0.2,
+        }
+    }
+}
+
+impl Dye {
+    pub fn omega(&self) -> f32 {
+        1.0 / (3.0 * self.diffusivity + 0.5)
+    }
+}
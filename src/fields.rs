@@ -0,0 +1,569 @@
+//! Macroscopic fields derived from a host-side copy of the distributions.
+
+use crate::bodies::RigidBody;
+use crate::collision::Collision;
+use crate::lattice::{self, CellType, C, Q, Q_THERMAL};
+use crate::parameters::Parameters;
+use crate::turbulence::Turbulence;
+
+/// Host-side initial state of the lattice.
+///
+/// Fluid and interface cells start in equilibrium with the given density and velocity, sink
+/// and source cells keep them as their prescribed state and walls move with their velocity.
+/// All per-cell arrays are stored in row-major order.
+#[derive(Clone, Debug)]
+pub struct InitialConditions {
+    pub size: [u32; 2],
+    pub density: Vec<f32>,
+    pub velocity: Vec<[f32; 2]>,
+    pub type_mask: Vec<u8>,
+    /// External force density, added to the uniform forces of the parameters.
+    pub force: Vec<[f32; 2]>,
+    /// Only used if the thermal parameters are set.
+    pub temperature: Vec<f32>,
+    /// Initial dye concentration of fluid cells and the concentration emitted by source
+    /// cells. Only used if the dye parameters are set.
+    pub dye: Vec<f32>,
+    /// Density of the second component, which starts with the velocity of the first one.
+    /// Only used by the two-component multiphase model.
+    pub second_density: Vec<f32>,
+    /// Fill level of interface cells, only used in the free-surface mode.
+    pub fill: Vec<f32>,
+    /// Fraction of the link in direction `i` that lies in front of a curved wall. Links with
+    /// a fraction of zero are bounced back halfway.
+    pub wall_distance: Vec<[f32; Q]>,
+    /// Index plus one of the obstacle a wall cell belongs to, zero for cells that aren't part
+    /// of one. Forces are summed per obstacle, see [`crate::forces`].
+    pub obstacle: Vec<u8>,
+    /// Obstacles moving under the forces of the flow, their cells are part of the type mask
+    /// and the obstacle indices at their initial pose.
+    pub bodies: Vec<RigidBody>,
+}
+
+impl InitialConditions {
+    /// A domain completely filled with fluid at rest.
+    pub fn new(size: [u32; 2]) -> Self {
+        let cells = (size[0] * size[1]) as usize;
+
+        Self {
+            size,
+            density: vec![1.0; cells],
+            velocity: vec![[0.0; 2]; cells],
+            type_mask: vec![CellType::Fluid as u8; cells],
+            force: vec![[0.0; 2]; cells],
+            temperature: vec![0.0; cells],
+            dye: vec![0.0; cells],
+            second_density: vec![0.0; cells],
+            fill: vec![1.0; cells],
+            wall_distance: vec![[0.0; Q]; cells],
+            obstacle: vec![0; cells],
+            bodies: Vec::new(),
+        }
+    }
+
+    /// Takes density, velocity and cell types from a snapshot, e.g. to restart a simulation.
+    pub fn from_fields(fields: &Fields) -> Self {
+        Self {
+            size: fields.size,
+            density: fields.density.clone(),
+            velocity: fields.velocity.clone(),
+            type_mask: fields.type_mask.clone(),
+            force: vec![[0.0; 2]; fields.density.len()],
+            temperature: fields
+                .temperature
+                .clone()
+                .unwrap_or_else(|| vec![0.0; fields.density.len()]),
+            dye: fields
+                .dye
+                .clone()
+                .unwrap_or_else(|| vec![0.0; fields.density.len()]),
+            second_density: fields
+                .second_density
+                .clone()
+                .unwrap_or_else(|| vec![0.0; fields.density.len()]),
+            fill: fields
+                .fill
+                .clone()
+                .unwrap_or_else(|| vec![1.0; fields.density.len()]),
+            wall_distance: vec![[0.0; Q]; fields.density.len()],
+            obstacle: vec![0; fields.density.len()],
+            bodies: Vec::new(),
+        }
+    }
+
+    pub fn index(&self, x: u32, y: u32) -> usize {
+        (y * self.size[0] + x) as usize
+    }
+
+    /// Sets up the cell types of the free-surface mode from the fill levels: fluid cells with
+    /// a fill level of zero become gas, partially filled ones and the ones next to gas become
+    /// interface cells. Neighbours don't wrap around the edges of the lattice.
+    pub fn close_interface(&mut self) {
+        let [width, height] = [self.size[0] as i32, self.size[1] as i32];
+
+        for (cell_type, &fill) in self.type_mask.iter_mut().zip(&self.fill) {
+            if *cell_type == CellType::Fluid as u8 && fill <= 0.0 {
+                *cell_type = CellType::Gas as u8;
+            } else if *cell_type == CellType::Fluid as u8 && fill < 1.0 {
+                *cell_type = CellType::Interface as u8;
+            }
+        }
+
+        let type_mask = self.type_mask.clone();
+
+        for y in 0..height {
+            for x in 0..width {
+                let cell = (y * width + x) as usize;
+
+                let next_to_gas = C.iter().any(|c| {
+                    let (nx, ny) = (x + c[0], y + c[1]);
+
+                    (0..width).contains(&nx)
+                        && (0..height).contains(&ny)
+                        && type_mask[(ny * width + nx) as usize] == CellType::Gas as u8
+                });
+
+                if type_mask[cell] == CellType::Fluid as u8 && next_to_gas {
+                    self.type_mask[cell] = CellType::Interface as u8;
+                }
+            }
+        }
+    }
+
+    /// Equilibrium distributions for the initial state, stored as `[i][y][x]`.
+    ///
+    /// Gas cells get them as well, interface cells take the populations they sent into the
+    /// gas from there.
+    pub fn distributions(&self) -> Vec<f32> {
+        let cells = (self.size[0] * self.size[1]) as usize;
+
+        assert_eq!(self.density.len(), cells);
+        assert_eq!(self.velocity.len(), cells);
+        assert_eq!(self.type_mask.len(), cells);
+
+        let mut distributions = vec![0.0; cells * Q];
+
+        for cell in 0..cells {
+            let cell_type = self.type_mask[cell];
+
+            if !CellType::is_liquid(cell_type) && cell_type != CellType::Gas as u8 {
+                continue;
+            }
+
+            let rho = self.density[cell];
+            let p = [rho * self.velocity[cell][0], rho * self.velocity[cell][1]];
+
+            for i in 0..Q {
+                distributions[i * cells + cell] = lattice::f_eq(i, rho, p);
+            }
+        }
+
+        distributions
+    }
+
+    /// Equilibrium distributions of the second component, stored as `[i][y][x]`.
+    pub fn second_distributions(&self) -> Vec<f32> {
+        let cells = (self.size[0] * self.size[1]) as usize;
+
+        assert_eq!(self.second_density.len(), cells);
+
+        let mut distributions = vec![0.0; cells * Q];
+
+        for cell in 0..cells {
+            let rho = self.second_density[cell];
+
+            if !CellType::is_liquid(self.type_mask[cell]) || rho <= 0.0 {
+                continue;
+            }
+
+            let p = [rho * self.velocity[cell][0], rho * self.velocity[cell][1]];
+
+            for i in 0..Q {
+                distributions[i * cells + cell] = lattice::f_eq(i, rho, p);
+            }
+        }
+
+        distributions
+    }
+
+    /// Equilibrium temperature distributions, stored as `[i][y][x]`.
+    pub fn thermal_distributions(&self) -> Vec<f32> {
+        self.scalar_distributions(&self.temperature)
+    }
+
+    /// Equilibrium dye distributions, stored as `[i][y][x]`.
+    pub fn dye_distributions(&self) -> Vec<f32> {
+        self.scalar_distributions(&self.dye)
+    }
+
+    /// D2Q5 equilibrium distributions of a scalar advected with the initial velocity.
+    fn scalar_distributions(&self, values: &[f32]) -> Vec<f32> {
+        let cells = (self.size[0] * self.size[1]) as usize;
+
+        assert_eq!(values.len(), cells);
+
+        let mut distributions = vec![0.0; cells * Q_THERMAL];
+
+        for cell in 0..cells {
+            if !CellType::is_liquid(self.type_mask[cell]) {
+                continue;
+            }
+
+            for i in 0..Q_THERMAL {
+                distributions[i * cells + cell] =
+                    lattice::g_eq(i, values[cell], self.velocity[cell]);
+            }
+        }
+
+        distributions
+    }
+
+    /// Per-cell state as stored in the `cell_data` image: density, velocity and temperature.
+    pub fn cell_data(&self) -> Vec<[f32; 4]> {
+        (0..self.density.len())
+            .map(|cell| {
+                let u = self.velocity[cell];
+
+                [self.density[cell], u[0], u[1], self.temperature[cell]]
+            })
+            .collect()
+    }
+
+    /// Free-surface state as stored in the `surface` images: mass, density and velocity. The
+    /// mass of fluid cells is their density, the one of interface cells follows from their
+    /// fill level.
+    pub fn surface(&self) -> Vec<[f32; 4]> {
+        (0..self.density.len())
+            .map(|cell| {
+                let rho = self.density[cell];
+                let u = self.velocity[cell];
+
+                let mass = match CellType::from_u8(self.type_mask[cell]) {
+                    Some(CellType::Fluid) => rho,
+                    Some(CellType::Interface) => self.fill[cell] * rho,
+                    _ => return [0.0; 4],
+                };
+
+                [mass, rho, u[0], u[1]]
+            })
+            .collect()
+    }
+
+    /// Per-cell force as stored in the `force_field` image, followed by the dye concentration
+    /// emitted by source cells.
+    pub fn force_field(&self) -> Vec<[f32; 4]> {
+        self.force
+            .iter()
+            .zip(&self.dye)
+            .map(|(f, &dye)| [f[0], f[1], dye, 0.0])
+            .collect()
+    }
+
+    /// Link fractions as stored in the `wall_distance` image, `[i][y][x]`.
+    pub fn wall_distances(&self) -> Vec<f32> {
+        (0..Q)
+            .flat_map(|i| self.wall_distance.iter().map(move |q| q[i]))
+            .collect()
+    }
+}
+
+/// Host-side snapshot of the lattice.
+///
+/// All per-cell arrays are stored in row-major order, `distributions` is stored as `[i][y][x]`.
+pub struct Fields {
+    pub size: [u32; 2],
+    pub distributions: Vec<f32>,
+    pub type_mask: Vec<u8>,
+    pub density: Vec<f32>,
+    pub velocity: Vec<[f32; 2]>,
+    /// Deviatoric stress `[xx, xy, yy]` from the non-equilibrium part of the distributions.
+    pub stress: Option<Vec<[f32; 3]>>,
+    /// Temperature, if the simulation has a temperature field.
+    pub temperature: Option<Vec<f32>>,
+    /// Dye concentration, if the simulation has dye.
+    pub dye: Option<Vec<f32>>,
+    /// Density of the second component, if the simulation has one.
+    pub second_density: Option<Vec<f32>>,
+    /// Fill level, one for fluid and zero for gas cells, in the free-surface mode.
+    pub fill: Option<Vec<f32>>,
+}
+
+impl Fields {
+    /// Derives density and velocity from `distributions`.
+    ///
+    /// Cells other than fluid and interface cells get a density of zero and no velocity.
+    pub fn from_distributions(size: [u32; 2], distributions: Vec<f32>, type_mask: Vec<u8>) -> Self {
+        let cells = (size[0] * size[1]) as usize;
+
+        assert_eq!(distributions.len(), cells * Q);
+        assert_eq!(type_mask.len(), cells);
+
+        let mut density = vec![0.0; cells];
+        let mut velocity = vec![[0.0; 2]; cells];
+
+        for cell in 0..cells {
+            if !CellType::is_liquid(type_mask[cell]) {
+                continue;
+            }
+
+            let f = |i: usize| distributions[i * cells + cell];
+
+            let mut rho = 0.0;
+            let mut p = [0.0, 0.0];
+
+            for (i, c) in C.iter().enumerate() {
+                rho += f(i);
+                p[0] += c[0] as f32 * f(i);
+                p[1] += c[1] as f32 * f(i);
+            }
+
+            density[cell] = rho;
+            velocity[cell] = [p[0] / rho, p[1] / rho];
+        }
+
+        Self {
+            size,
+            distributions,
+            type_mask,
+            density,
+            velocity,
+            stress: None,
+            temperature: None,
+            dye: None,
+            second_density: None,
+            fill: None,
+        }
+    }
+
+    /// Derives the temperature of fluid cells from the D2Q5 distributions, stored as
+    /// `[i][y][x]`.
+    pub fn compute_temperature(&mut self, thermal_distributions: &[f32]) {
+        self.temperature = Some(self.scalar_field(thermal_distributions));
+    }
+
+    /// Derives the dye concentration of fluid cells from the D2Q5 distributions, stored as
+    /// `[i][y][x]`.
+    pub fn compute_dye(&mut self, dye_distributions: &[f32]) {
+        self.dye = Some(self.scalar_field(dye_distributions));
+    }
+
+    /// Derives the density of the second component from its D2Q9 distributions, stored as
+    /// `[i][y][x]`.
+    pub fn compute_second_density(&mut self, second_distributions: &[f32]) {
+        let cells = self.density.len();
+
+        assert_eq!(second_distributions.len(), cells * Q);
+
+        let density = (0..cells)
+            .map(|cell| {
+                if !CellType::is_liquid(self.type_mask[cell]) {
+                    return 0.0;
+                }
+
+                (0..Q).map(|i| second_distributions[i * cells + cell]).sum()
+            })
+            .collect();
+
+        self.second_density = Some(density);
+    }
+
+    /// Derives the fill level from the free-surface state in the layout of
+    /// [`InitialConditions::surface`].
+    pub fn compute_fill(&mut self, surface: &[[f32; 4]]) {
+        assert_eq!(surface.len(), self.density.len());
+
+        let fill = surface
+            .iter()
+            .zip(&self.type_mask)
+            .map(|(state, &cell_type)| match CellType::from_u8(cell_type) {
+                Some(CellType::Fluid) => 1.0,
+                Some(CellType::Interface) => state[0] / state[1],
+                _ => 0.0,
+            })
+            .collect();
+
+        self.fill = Some(fill);
+    }
+
+    fn scalar_field(&self, distributions: &[f32]) -> Vec<f32> {
+        let cells = self.density.len();
+
+        assert_eq!(distributions.len(), cells * Q_THERMAL);
+
+        (0..cells)
+            .map(|cell| {
+                if !CellType::is_liquid(self.type_mask[cell]) {
+                    return 0.0;
+                }
+
+                (0..Q_THERMAL)
+                    .map(|i| distributions[i * cells + cell])
+                    .sum()
+            })
+            .collect()
+    }
+
+    /// Adds half the force acting on each cell to the velocity, as the Guo forcing scheme
+    /// does for its equilibrium. Has to be called after `compute_temperature` and before
+    /// `compute_stress`.
+    ///
+    /// Only gravity, buoyancy, the uniform force and `force_field` are included. The Shan–Chen
+    /// interaction of multiphase simulations and the brush aren't, in cells they act on the
+    /// velocity differs from the one the kernel uses by half their force.
+    pub fn apply_force(&mut self, parameters: &Parameters, force_field: Option<&[[f32; 2]]>) {
+        for (cell, velocity) in self.velocity.iter_mut().enumerate() {
+            if !CellType::is_liquid(self.type_mask[cell]) {
+                continue;
+            }
+
+            let rho = self.density[cell];
+            let field = force_field.map_or([0.0; 2], |field| field[cell]);
+
+            let buoyancy = match (&parameters.thermal, &self.temperature) {
+                (Some(thermal), Some(temperature)) => {
+                    let excess = temperature[cell] - thermal.reference_temperature;
+
+                    [thermal.buoyancy[0] * excess, thermal.buoyancy[1] * excess]
+                }
+                _ => [0.0; 2],
+            };
+
+            for d in 0..2 {
+                let force =
+                    rho * (parameters.gravity[d] + buoyancy[d]) + parameters.force[d] + field[d];
+
+                velocity[d] += 0.5 * force / rho;
+            }
+        }
+    }
+
+    /// Computes the deviatoric stress for the default entropic kernel relaxing with `beta`.
+    pub fn compute_stress(&mut self, beta: f32) {
+        self.compute_stress_from(Collision::Entropic, |_, _| beta);
+    }
+
+    /// Computes the deviatoric stress for a kernel relaxing with the `beta`, collision operator,
+    /// rheology and turbulence model of `parameters`.
+    ///
+    /// The WALE model needs the velocity gradient across neighbouring cells and isn't included,
+    /// its stress is that of the molecular viscosity alone. Like the velocity, see
+    /// [`Fields::apply_force`], the stress doesn't include the multiphase and brush forces.
+    pub fn compute_stress_with(&mut self, parameters: &Parameters) {
+        self.compute_stress_from(parameters.collision, |rho, pi| {
+            let beta = parameters.rheology.effective_beta(parameters.beta, rho, pi);
+
+            match parameters.turbulence {
+                Turbulence::Smagorinsky { .. } => {
+                    parameters
+                        .turbulence
+                        .effective_beta(beta, rho, pi, [[0.0; 2]; 2])
+                }
+                _ => beta,
+            }
+        });
+    }
+
+    /// Computes the deviatoric stress with the local `beta` given by `beta(rho, pi)`, where `pi`
+    /// is the momentum flux `(xx, xy, yy)` off the equilibrium of `collision`.
+    fn compute_stress_from(&mut self, collision: Collision, beta: impl Fn(f32, [f32; 3]) -> f32) {
+        let cells = self.density.len();
+
+        let mut stress = vec![[0.0; 3]; cells];
+
+        for (cell, stress) in stress.iter_mut().enumerate() {
+            if !CellType::is_liquid(self.type_mask[cell]) {
+                continue;
+            }
+
+            let rho = self.density[cell];
+            let p = [rho * self.velocity[cell][0], rho * self.velocity[cell][1]];
+
+            let mut pi = [0.0; 3];
+
+            for (i, c) in C.iter().enumerate() {
+                let f_neq = self.distributions[i * cells + cell] - collision.f_eq(i, rho, p);
+                let [cx, cy] = [c[0] as f32, c[1] as f32];
+
+                pi[0] += cx * cx * f_neq;
+                pi[1] += cx * cy * f_neq;
+                pi[2] += cy * cy * f_neq;
+            }
+
+            let beta = beta(rho, pi);
+
+            // The kernel relaxes with omega = 2 beta, so 1 - omega / 2 = 1 - beta.
+            let factor = -(1.0 - beta);
+
+            *stress = [factor * pi[0], factor * pi[1], factor * pi[2]];
+        }
+
+        self.stress = Some(stress);
+    }
+
+    pub fn index(&self, x: u32, y: u32) -> usize {
+        (y * self.size[0] + x) as usize
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A single fluid cell holding the equilibrium of `collision`.
+    fn equilibrium(collision: Collision, u: [f32; 2]) -> Fields {
+        let distributions = (0..Q).map(|i| collision.f_eq(i, 1.0, u)).collect();
+
+        Fields::from_distributions([1, 1], distributions, vec![CellType::Fluid as u8])
+    }
+
+    #[test]
+    fn stress_vanishes_at_the_equilibrium_of_the_operator() {
+        let u = [0.2, 0.1];
+
+        for collision in [Collision::Entropic, Collision::Bgk, Collision::Regularized] {
+            let parameters = Parameters {
+                collision,
+                ..Parameters::default()
+            };
+
+            let mut fields = equilibrium(collision, u);
+            fields.compute_stress_with(&parameters);
+
+            let stress = fields.stress.unwrap()[0];
+
+            assert!(
+                stress.iter().all(|s| s.abs() < 1e-6),
+                "{:?} gave {:?}",
+                collision,
+                stress
+            );
+        }
+
+        // The equilibria differ at this velocity, the entropic one is off the polynomial one.
+        let mut fields = equilibrium(Collision::Entropic, u);
+        fields.compute_stress_with(&Parameters {
+            collision: Collision::Bgk,
+            ..Parameters::default()
+        });
+
+        assert!(fields.stress.unwrap()[0].iter().any(|s| s.abs() > 1e-5));
+    }
+
+    #[test]
+    fn half_the_force_is_added_to_the_velocity() {
+        let mut fields = equilibrium(Collision::Entropic, [0.0, 0.0]);
+
+        let parameters = Parameters {
+            gravity: [0.0, 0.01],
+            force: [0.02, 0.0],
+            ..Parameters::default()
+        };
+
+        fields.apply_force(&parameters, Some(&[[0.0, 0.04]]));
+
+        let velocity = fields.velocity[0];
+
+        assert!((velocity[0] - 0.01).abs() < 1e-6);
+        assert!((velocity[1] - 0.025).abs() < 1e-6);
+    }
+}
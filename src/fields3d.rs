@@ -0,0 +1,154 @@
+//! Initial state and macroscopic fields of the 3D lattice.
+
+use crate::lattice::CellType;
+use crate::lattice3d::{VelocitySet, C};
+use crate::parameters::Parameters3d;
+
+/// Host-side initial state of a 3D lattice.
+///
+/// Fluid cells start in equilibrium with the given density and velocity, sink and source cells
+/// keep them as their prescribed state and walls move with their velocity. All per-cell arrays
+/// are stored as `[z][y][x]`.
+#[derive(Clone, Debug)]
+pub struct InitialConditions3d {
+    pub size: [u32; 3],
+    pub density: Vec<f32>,
+    pub velocity: Vec<[f32; 3]>,
+    pub type_mask: Vec<u8>,
+}
+
+impl InitialConditions3d {
+    /// A domain completely filled with fluid at rest.
+    pub fn new(size: [u32; 3]) -> Self {
+        let cells = (size[0] * size[1] * size[2]) as usize;
+
+        Self {
+            size,
+            density: vec![1.0; cells],
+            velocity: vec![[0.0; 3]; cells],
+            type_mask: vec![CellType::Fluid as u8; cells],
+        }
+    }
+
+    pub fn index(&self, x: u32, y: u32, z: u32) -> usize {
+        ((z * self.size[1] + y) * self.size[0] + x) as usize
+    }
+
+    /// Equilibrium distributions for the initial state, stored as `[i][z][y][x]`.
+    pub fn distributions(&self, velocity_set: VelocitySet) -> Vec<f32> {
+        let cells = self.density.len();
+
+        assert_eq!(self.velocity.len(), cells);
+        assert_eq!(self.type_mask.len(), cells);
+
+        let mut distributions = vec![0.0; cells * velocity_set.q()];
+
+        for cell in 0..cells {
+            if self.type_mask[cell] != CellType::Fluid as u8 {
+                continue;
+            }
+
+            let rho = self.density[cell];
+            let u = self.velocity[cell];
+            let p = [rho * u[0], rho * u[1], rho * u[2]];
+
+            for i in 0..velocity_set.q() {
+                distributions[i * cells + cell] = velocity_set.f_eq(i, rho, p);
+            }
+        }
+
+        distributions
+    }
+
+    /// Per-cell state as stored in the `cell_data` image: density and velocity.
+    pub fn cell_data(&self) -> Vec<[f32; 4]> {
+        self.density
+            .iter()
+            .zip(&self.velocity)
+            .map(|(&rho, u)| [rho, u[0], u[1], u[2]])
+            .collect()
+    }
+}
+
+/// Host-side snapshot of a 3D lattice.
+///
+/// All per-cell arrays are stored as `[z][y][x]`, `distributions` is stored as `[i][z][y][x]`.
+pub struct Fields3d {
+    pub size: [u32; 3],
+    pub distributions: Vec<f32>,
+    pub type_mask: Vec<u8>,
+    pub density: Vec<f32>,
+    pub velocity: Vec<[f32; 3]>,
+}
+
+impl Fields3d {
+    /// Derives density and velocity from `distributions`.
+    ///
+    /// Cells other than fluid cells get a density of zero and no velocity.
+    pub fn from_distributions(
+        size: [u32; 3],
+        velocity_set: VelocitySet,
+        distributions: Vec<f32>,
+        type_mask: Vec<u8>,
+    ) -> Self {
+        let cells = (size[0] * size[1] * size[2]) as usize;
+
+        assert_eq!(distributions.len(), cells * velocity_set.q());
+        assert_eq!(type_mask.len(), cells);
+
+        let mut density = vec![0.0; cells];
+        let mut velocity = vec![[0.0; 3]; cells];
+
+        for cell in 0..cells {
+            if type_mask[cell] != CellType::Fluid as u8 {
+                continue;
+            }
+
+            let mut rho = 0.0;
+            let mut p = [0.0; 3];
+
+            for (i, c) in C.iter().enumerate().take(velocity_set.q()) {
+                let f = distributions[i * cells + cell];
+
+                rho += f;
+
+                for d in 0..3 {
+                    p[d] += c[d] as f32 * f;
+                }
+            }
+
+            density[cell] = rho;
+            velocity[cell] = [p[0] / rho, p[1] / rho, p[2] / rho];
+        }
+
+        Self {
+            size,
+            distributions,
+            type_mask,
+            density,
+            velocity,
+        }
+    }
+
+    /// Adds half the force acting on each cell to the velocity, as the Guo forcing scheme
+    /// does for its equilibrium.
+    pub fn apply_force(&mut self, parameters: &Parameters3d) {
+        for (cell, velocity) in self.velocity.iter_mut().enumerate() {
+            if self.type_mask[cell] != CellType::Fluid as u8 {
+                continue;
+            }
+
+            let rho = self.density[cell];
+
+            for (d, velocity) in velocity.iter_mut().enumerate() {
+                let force = rho * parameters.gravity[d] + parameters.force[d];
+
+                *velocity += 0.5 * force / rho;
+            }
+        }
+    }
+
+    pub fn index(&self, x: u32, y: u32, z: u32) -> usize {
+        ((z * self.size[1] + y) * self.size[0] + x) as usize
+    }
+}
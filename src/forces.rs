@@ -0,0 +1,156 @@
+//! Forces and moments the flow exerts on obstacles, measured with the momentum-exchange method
+//! at the links between fluid and wall cells.
+
+/// Number of obstacles whose forces are summed separately. Wall cells of further obstacles
+/// don't contribute to any of them.
+pub const MAX_OBSTACLES: usize = 8;
+
+/// Number of steps the GPU keeps in its force buffer, samples older than that are overwritten
+/// before they are read.
+pub const FORCE_HISTORY: usize = 4096;
+
+/// Force and moment on one obstacle during a step, in lattice units.
+///
+/// The moment is taken about the centroid of the obstacle cells or the center of mass of a
+/// moving obstacle, positive values turn the obstacle clockwise on screen like a positive
+/// `angular_velocity` of a scene obstacle.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct ObstacleForce {
+    pub force: [f32; 2],
+    pub moment: f32,
+}
+
+impl ObstacleForce {
+    /// Adds a force acting at `r` relative to the reference point of the moment, e.g. the
+    /// momentum a population exchanges with a wall cell.
+    pub fn add(&mut self, force: [f32; 2], r: [f32; 2]) {
+        self.force[0] += force[0];
+        self.force[1] += force[1];
+        self.moment += r[0] * force[1] - r[1] * force[0];
+    }
+}
+
+/// Forces on all obstacles of a scene after a step.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ForceSample {
+    /// Number of steps since the last reset.
+    pub step: u64,
+    pub obstacles: Vec<ObstacleForce>,
+}
+
+/// Centroids of the obstacles in cells, for a mask holding the obstacle index plus one of wall
+/// cells and zero elsewhere. Also returns the number of obstacles, the highest index found.
+pub fn centroids(obstacle_mask: &[u8], size: [u32; 2]) -> ([[f32; 2]; MAX_OBSTACLES], usize) {
+    let mut sums = [[0.0f64; 3]; MAX_OBSTACLES];
+    let mut count = 0;
+
+    for (cell, &obstacle) in obstacle_mask.iter().enumerate() {
+        if obstacle == 0 || obstacle as usize > MAX_OBSTACLES {
+            continue;
+        }
+
+        let sum = &mut sums[obstacle as usize - 1];
+
+        sum[0] += (cell % size[0] as usize) as f64;
+        sum[1] += (cell / size[0] as usize) as f64;
+        sum[2] += 1.0;
+
+        count = count.max(obstacle as usize);
+    }
+
+    let mut centroids = [[0.0; 2]; MAX_OBSTACLES];
+
+    for (centroid, sum) in centroids.iter_mut().zip(&sums) {
+        if sum[2] > 0.0 {
+            *centroid = [(sum[0] / sum[2]) as f32, (sum[1] / sum[2]) as f32];
+        }
+    }
+
+    (centroids, count)
+}
+
+/// Drag or lift coefficient `2 F / (rho U² L)` of a force per unit depth, with all values in
+/// lattice units.
+pub fn force_coefficient(force: f32, density: f32, velocity: f32, length: f32) -> f32 {
+    2.0 * force / (density * velocity * velocity * length)
+}
+
+/// Frequency of a periodic signal such as the lift of a cylinder shedding vortices, in cycles
+/// per sample. Counts the upward crossings of the mean and interpolates between the samples
+/// around them. `None` if there are fewer than two crossings.
+pub fn dominant_frequency(signal: &[f32]) -> Option<f32> {
+    let mean = signal.iter().sum::<f32>() / signal.len() as f32;
+
+    let crossings: Vec<f32> = signal
+        .windows(2)
+        .enumerate()
+        .filter(|(_, pair)| pair[0] < mean && pair[1] >= mean)
+        .map(|(i, pair)| i as f32 + (mean - pair[0]) / (pair[1] - pair[0]))
+        .collect();
+
+    match (crossings.first(), crossings.last()) {
+        (Some(first), Some(last)) if crossings.len() >= 2 => {
+            Some((crossings.len() - 1) as f32 / (last - first))
+        }
+        _ => None,
+    }
+}
+
+/// Strouhal number `f L / U` of a frequency in cycles per step, with length and velocity in
+/// lattice units.
+pub fn strouhal_number(frequency: f32, velocity: f32, length: f32) -> f32 {
+    frequency * length / velocity
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn forces_add_up_with_their_moments() {
+        let mut total = ObstacleForce::default();
+
+        total.add([1.0, 0.0], [0.0, -1.0]);
+        total.add([0.0, 2.0], [1.0, 0.0]);
+
+        assert_eq!(total.force, [1.0, 2.0]);
+        assert_eq!(total.moment, 3.0);
+    }
+
+    #[test]
+    fn centroids_of_obstacle_cells() {
+        // Obstacle 1 covers the cells (0, 0) and (2, 0), obstacle 3 the cell (1, 1).
+        let mask = [1, 0, 1, 0, 3, 0];
+
+        let (centroids, count) = centroids(&mask, [3, 2]);
+
+        assert_eq!(count, 3);
+        assert_eq!(centroids[0], [1.0, 0.0]);
+        assert_eq!(centroids[1], [0.0, 0.0]);
+        assert_eq!(centroids[2], [1.0, 1.0]);
+    }
+
+    #[test]
+    fn coefficients_are_normalized_by_the_dynamic_pressure() {
+        assert!((force_coefficient(0.5, 1.0, 0.1, 50.0) - 2.0).abs() < 1e-5);
+        assert!((strouhal_number(0.002, 0.1, 10.0) - 0.2).abs() < 1e-6);
+    }
+
+    #[test]
+    fn frequency_of_a_sine() {
+        let signal: Vec<f32> = (0..1000)
+            .map(|step| (step as f32 * std::f32::consts::TAU / 40.0 + 0.3).sin())
+            .collect();
+
+        let frequency = dominant_frequency(&signal).unwrap();
+
+        assert!((frequency - 1.0 / 40.0).abs() < 1e-4, "f = {}", frequency);
+    }
+
+    #[test]
+    fn no_frequency_without_oscillation() {
+        assert_eq!(dominant_frequency(&[]), None);
+        assert_eq!(dominant_frequency(&[1.0; 100]), None);
+        assert_eq!(dominant_frequency(&[0.0, 1.0, 2.0, 3.0]), None);
+    }
+}
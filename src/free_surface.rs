@@ -0,0 +1,45 @@
+//! Free-surface flows with cell-based interface tracking (Körner et al. 2005).
+//!
+//! Liquid cells are either fluid or interface cells, the rest of the domain is gas that isn't
+//! simulated. Interface cells track their mass, exchanged with their liquid neighbours while
+//! streaming, and receive the populations coming from the gas such that they balance the gas
+//! pressure. An interface cell that fills up turns into a fluid cell and its gas neighbours
+//! into interface cells, one that empties turns into gas and its fluid neighbours into
+//! interface cells. Excess mass of converted cells goes to the neighbouring interface cells.
+
+use serde::Deserialize;
+
+use crate::lattice::CellType;
+
+#[derive(Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(default)]
+pub struct FreeSurface {
+    /// Density corresponding to the gas pressure, usually the density of the liquid at rest.
+    pub gas_density: f32,
+}
+
+impl Default for FreeSurface {
+    fn default() -> Self {
+        Self { gas_density: 1.0 }
+    }
+}
+
+/// Interface cells convert once their mass leaves `[-FILL_TOLERANCE, 1 + FILL_TOLERANCE]` times
+/// their density.
+pub(crate) const FILL_TOLERANCE: f32 = 1e-3;
+
+/// Interface cells without fluid neighbours already turn into gas below this fill level,
+/// isolated ones right away.
+pub(crate) const LONELY_FILL: f32 = 0.1;
+
+// Transitional types of interface cells that filled up or emptied and of the cells around
+// them, only present during a step.
+pub(crate) const INTERFACE_TO_FLUID: u8 = 8;
+pub(crate) const INTERFACE_TO_GAS: u8 = 9;
+pub(crate) const GAS_TO_INTERFACE: u8 = 10;
+pub(crate) const FLUID_TO_INTERFACE: u8 = 11;
+
+/// Interface cells including the ones marked for conversion during the current step.
+pub(crate) fn is_interface(value: u8) -> bool {
+    value == CellType::Interface as u8 || value == INTERFACE_TO_FLUID || value == INTERFACE_TO_GAS
+}
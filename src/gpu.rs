@@ -7,24 +7,29 @@ use std::sync::Arc;
 
 use derive_getters::Getters;
 use thiserror::Error;
+use vulkano::buffer::cpu_access::ReadLockError;
+use vulkano::buffer::{BufferAccess, BufferUsage, CpuAccessibleBuffer};
 use vulkano::command_buffer::{
-    AutoCommandBufferBuilder, BeginRenderPassError, BuildError, CommandBufferExecError,
-    CommandBufferUsage, DispatchError, DrawError, ExecuteCommandsError, SecondaryAutoCommandBuffer,
-    SubpassContents,
+    AutoCommandBufferBuilder, BeginRenderPassError, BuildError, ClearColorImageError,
+    CommandBufferExecError, CommandBufferUsage, CopyBufferImageError, DispatchError, DrawError,
+    ExecuteCommandsError, SecondaryAutoCommandBuffer, SubpassContents,
 };
 use vulkano::descriptor_set::layout::DescriptorSetLayout;
 use vulkano::descriptor_set::persistent::PersistentDescriptorSetBuilder;
 use vulkano::descriptor_set::{DescriptorSetError, PersistentDescriptorSet};
 use vulkano::device::physical::{PhysicalDevice, QueueFamily};
 use vulkano::device::{Device, DeviceCreationError, DeviceExtensions, Features, Queue};
+use vulkano::format::{ClearValue, NumericType, Pixel};
 use vulkano::image::view::{ImageView, ImageViewCreationError};
 use vulkano::image::{
-    ImageAccess, ImageLayout, ImageUsage, ImageViewAbstract, SampleCount, SwapchainImage,
+    ImageAccess, ImageLayout, ImageUsage, ImageViewAbstract, SampleCount, StorageImage,
+    SwapchainImage,
 };
 use vulkano::instance::debug::{
     DebugCallback, DebugCallbackCreationError, MessageSeverity, MessageType,
 };
 use vulkano::instance::{Instance, InstanceCreationError, InstanceExtensions};
+use vulkano::memory::DeviceMemoryAllocError;
 use vulkano::pipeline::layout::PipelineLayout;
 use vulkano::pipeline::shader::{
     ComputeEntryPoint, EntryPointAbstract, GraphicsEntryPoint, ShaderModule,
@@ -46,7 +51,7 @@ use vulkano::swapchain::{
     acquire_next_image, present, AcquireError, CapabilitiesError, PresentMode, Surface,
     SurfaceCreationError, Swapchain, SwapchainCreationError,
 };
-use vulkano::sync::{self, GpuFuture};
+use vulkano::sync::{self, FlushError, GpuFuture};
 use vulkano::{OomError, Version};
 use winit::dpi::PhysicalSize;
 use winit::window::{Fullscreen, Window};
@@ -76,18 +81,23 @@ pub enum ContextCreationError {
 type TargetImage = Arc<SwapchainImage<Arc<Window>>>;
 
 #[derive(Clone)]
-pub struct Context {
+struct Presentation {
     window: Arc<Window>,
-    instance: Arc<Instance>,
-    phys_index: usize,
-    device: Arc<Device>,
-    queue: Arc<Queue>,
     surface: Arc<Surface<Arc<Window>>>,
     swapchain: Arc<Swapchain<Arc<Window>>>,
     swapchain_images: Arc<Vec<TargetImage>>,
     swapchain_image_views: Arc<Vec<Arc<ImageView<TargetImage>>>>,
 }
 
+#[derive(Clone)]
+pub struct Context {
+    instance: Arc<Instance>,
+    phys_index: usize,
+    device: Arc<Device>,
+    queue: Arc<Queue>,
+    presentation: Option<Presentation>,
+}
+
 impl Context {
     pub fn new(window: &Arc<Window>) -> Result<Self, ContextCreationError> {
         window.set_resizable(false);
@@ -110,29 +120,14 @@ impl Context {
 
         let queue_families = QueueFamilies::find(&physical_device, &surface)?;
 
-        let (device, queue) = {
-            let features = Features {
-                shader_storage_image_extended_formats: true,
-                ..Features::none()
-            };
-
-            let device_extensions = DeviceExtensions {
+        let (device, queue) = create_device(
+            physical_device,
+            DeviceExtensions {
                 khr_swapchain: true,
-                khr_storage_buffer_storage_class: true,
                 ..DeviceExtensions::none()
-            };
-
-            let (device, mut queues) = Device::new(
-                physical_device,
-                &features,
-                &device_extensions,
-                queue_families.as_vec(),
-            )?;
-
-            let queue = queues.next().unwrap();
-
-            (device, queue)
-        };
+            },
+            &queue_families,
+        )?;
 
         let caps = surface.capabilities(physical_device)?;
 
@@ -148,20 +143,52 @@ impl Context {
             .collect::<Result<Vec<_>, _>>()?;
 
         Ok(Self {
-            window: window.clone(),
             instance,
             phys_index,
             device,
             queue,
-            surface,
-            swapchain,
-            swapchain_images: Arc::new(swapchain_images),
-            swapchain_image_views: Arc::new(image_views),
+            presentation: Some(Presentation {
+                window: window.clone(),
+                surface,
+                swapchain,
+                swapchain_images: Arc::new(swapchain_images),
+                swapchain_image_views: Arc::new(image_views),
+            }),
         })
     }
 
-    pub fn window(&self) -> Arc<Window> {
-        self.window.clone()
+    /// Creates a context without a window, surface or swapchain.
+    ///
+    /// Only a compute capable queue is requested, so this also works with software drivers
+    /// like lavapipe. Such a context can drive a [`ComputeProgram`] but not a [`Renderer`].
+    pub fn headless() -> Result<Self, ContextCreationError> {
+        let instance = Instance::new(None, Version::V1_2, &InstanceExtensions::none(), [])?;
+
+        let (phys_index, physical_device) = PhysicalDevice::enumerate(&instance)
+            .enumerate()
+            .find(|(_, device)| device.queue_families().any(|qf| qf.supports_compute()))
+            .ok_or(ContextCreationError::NoGPUAvailable)?;
+
+        let queue_families = QueueFamilies::find_compute(&physical_device)?;
+
+        let (device, queue) =
+            create_device(physical_device, DeviceExtensions::none(), &queue_families)?;
+
+        Ok(Self {
+            instance,
+            phys_index,
+            device,
+            queue,
+            presentation: None,
+        })
+    }
+
+    pub fn is_headless(&self) -> bool {
+        self.presentation.is_none()
+    }
+
+    pub fn window(&self) -> Option<Arc<Window>> {
+        self.presentation.as_ref().map(|p| p.window.clone())
     }
 
     pub fn instance(&self) -> Arc<Instance> {
@@ -180,25 +207,57 @@ impl Context {
         self.queue.clone()
     }
 
-    pub fn surface(&self) -> Arc<Surface<Arc<Window>>> {
-        self.surface.clone()
+    pub fn surface(&self) -> Option<Arc<Surface<Arc<Window>>>> {
+        self.presentation.as_ref().map(|p| p.surface.clone())
     }
 
-    pub fn swapchain(&self) -> Arc<Swapchain<Arc<Window>>> {
-        self.swapchain.clone()
+    pub fn swapchain(&self) -> Option<Arc<Swapchain<Arc<Window>>>> {
+        self.presentation.as_ref().map(|p| p.swapchain.clone())
     }
 
-    pub fn swapchain_images(&self) -> Arc<Vec<Arc<SwapchainImage<Arc<Window>>>>> {
-        self.swapchain_images.clone()
+    pub fn swapchain_images(&self) -> Option<Arc<Vec<TargetImage>>> {
+        self.presentation
+            .as_ref()
+            .map(|p| p.swapchain_images.clone())
     }
 
-    pub fn swapchain_image_views(&self) -> Arc<Vec<Arc<ImageView<TargetImage>>>> {
-        self.swapchain_image_views.clone()
+    pub fn swapchain_image_views(&self) -> Option<Arc<Vec<Arc<ImageView<TargetImage>>>>> {
+        self.presentation
+            .as_ref()
+            .map(|p| p.swapchain_image_views.clone())
     }
 }
 
+fn create_device(
+    physical_device: PhysicalDevice,
+    extensions: DeviceExtensions,
+    queue_families: &QueueFamilies,
+) -> Result<(Arc<Device>, Arc<Queue>), ContextCreationError> {
+    let features = Features {
+        shader_storage_image_extended_formats: true,
+        ..Features::none()
+    };
+
+    let device_extensions = DeviceExtensions {
+        khr_storage_buffer_storage_class: true,
+        ..extensions
+    };
+
+    let (device, mut queues) = Device::new(
+        physical_device,
+        &features,
+        &device_extensions,
+        queue_families.as_vec(),
+    )?;
+
+    let queue = queues.next().unwrap();
+
+    Ok((device, queue))
+}
+
 struct QueueFamilies<'a> {
-    graphics: QueueFamily<'a>,
+    /// `None` for headless contexts, which only need a compute queue.
+    graphics: Option<QueueFamily<'a>>,
     compute: QueueFamily<'a>,
 }
 
@@ -215,18 +274,33 @@ impl<'a> QueueFamilies<'a> {
         let compute_family = graphics_family;
 
         Ok(Self {
-            graphics: graphics_family,
+            graphics: Some(graphics_family),
+            compute: compute_family,
+        })
+    }
+
+    fn find_compute(phys_device: &PhysicalDevice<'a>) -> Result<Self, ContextCreationError> {
+        let compute_family = phys_device
+            .queue_families()
+            .find(|qf| qf.supports_compute())
+            .ok_or(ContextCreationError::NoQueueFamilyFound)?;
+
+        Ok(Self {
+            graphics: None,
             compute: compute_family,
         })
     }
 
     fn as_vec(&self) -> Vec<(QueueFamily, f32)> {
-        vec![(self.graphics, 1.0)]
+        // A windowed context renders and computes on the same queue.
+        vec![(self.graphics.unwrap_or(self.compute), 1.0)]
     }
 }
 #[allow(clippy::enum_variant_names)]
 #[derive(Error, Debug)]
 pub enum RendererCreationError {
+    #[error("Context has no swapchain to render to.")]
+    NoSwapchain,
     #[error("Failed to create render pass.")]
     RenderPassCreationError(#[from] RenderPassCreationError),
     #[error("Failed to create graphics pipeline.")]
@@ -256,6 +330,8 @@ pub enum RendererDrawError {
 
 pub struct Renderer {
     context: Context,
+    swapchain: Arc<Swapchain<Arc<Window>>>,
+    swapchain_image_views: Arc<Vec<Arc<ImageView<TargetImage>>>>,
     render_pass: Arc<RenderPass>,
     pipeline: Arc<GraphicsPipeline>,
     sampler: Arc<Sampler>,
@@ -269,11 +345,19 @@ impl Renderer {
     ) -> Result<Self, RendererCreationError> {
         let context = context.clone();
 
+        let swapchain = context
+            .swapchain()
+            .ok_or(RendererCreationError::NoSwapchain)?;
+
+        let swapchain_image_views = context
+            .swapchain_image_views()
+            .ok_or(RendererCreationError::NoSwapchain)?;
+
         let render_pass = Arc::new(RenderPass::new(
             context.device(),
             RenderPassDesc::new(
                 vec![AttachmentDesc {
-                    format: context.swapchain().format(),
+                    format: swapchain.format(),
                     samples: SampleCount::Sample1,
                     load: LoadOp::Clear,
                     store: StoreOp::Store,
@@ -320,6 +404,8 @@ impl Renderer {
 
         Ok(Self {
             context,
+            swapchain,
+            swapchain_image_views,
             render_pass,
             pipeline,
             sampler,
@@ -332,25 +418,25 @@ impl Renderer {
         before: Box<dyn GpuFuture>,
         push_constants: Pc,
     ) -> Result<Box<dyn GpuFuture>, RendererDrawError> {
-        let (image_index, _, image_future) =
-            match acquire_next_image(self.context.swapchain(), None) {
-                Err(AcquireError::OutOfDate) => {
-                    //let caps = self.context.surface().capabilities(self.context.physical_device()).unwrap();
-
-                    self.context.swapchain().recreate().build();
-                    acquire_next_image(self.context.swapchain(), None).unwrap()
-                }
-                Ok(t) => t,
-                Err(err) => panic!("{:?}", err),
-            };
+        let (image_index, _, image_future) = match acquire_next_image(self.swapchain.clone(), None)
+        {
+            Err(AcquireError::OutOfDate) => {
+                //let caps = self.context.surface().capabilities(self.context.physical_device()).unwrap();
+
+                self.swapchain.recreate().build();
+                acquire_next_image(self.swapchain.clone(), None).unwrap()
+            }
+            Ok(t) => t,
+            Err(err) => panic!("{:?}", err),
+        };
 
         let framebuffer = Arc::new(
             Framebuffer::start(self.render_pass.clone())
-                .add(self.context.swapchain_image_views()[image_index].clone())?
+                .add(self.swapchain_image_views[image_index].clone())?
                 .build()?,
         );
 
-        let dimensions = self.context.swapchain().dimensions();
+        let dimensions = self.swapchain.dimensions();
 
         let mut command_buffer_builder = AutoCommandBufferBuilder::primary(
             self.context.device(),
@@ -394,7 +480,7 @@ impl Renderer {
         let future = before
             .join(image_future)
             .then_execute(self.context.queue(), command_buffer)?
-            .then_swapchain_present(self.context.queue(), self.context.swapchain(), image_index)
+            .then_swapchain_present(self.context.queue(), self.swapchain.clone(), image_index)
             .then_signal_fence_and_flush()
             .unwrap();
 
@@ -412,6 +498,8 @@ pub enum ComputeProgramCreationError {
 pub enum ComputeError {
     #[error("Shader doesn't have an image descriptor.")]
     NoImageDescriptor,
+    #[error("Shader doesn't have a buffer descriptor.")]
+    NoBufferDescriptor,
     #[error("Failed to execute command buffer.")]
     CommandBufferExecError(#[from] CommandBufferExecError),
     #[error("Failed to create descriptor set.")]
@@ -448,18 +536,31 @@ impl ComputeProgram {
         })
     }
 
+    /// Dispatches the shader with `images` bound to set 0.
     pub fn compute<Pc>(
         &self,
         images: &[Arc<dyn ImageViewAbstract>],
         dispatch_dimensions: [u32; 3],
         push_constants: Pc,
         before: Box<dyn GpuFuture>,
+    ) -> Result<Box<dyn GpuFuture>, ComputeError> {
+        self.compute_with_buffers(images, &[], dispatch_dimensions, push_constants, before)
+    }
+
+    /// Dispatches the shader with `images` bound to set 0 and `buffers` bound to set 1.
+    pub fn compute_with_buffers<Pc>(
+        &self,
+        images: &[Arc<dyn ImageViewAbstract>],
+        buffers: &[Arc<dyn BufferAccess>],
+        dispatch_dimensions: [u32; 3],
+        push_constants: Pc,
+        before: Box<dyn GpuFuture>,
     ) -> Result<Box<dyn GpuFuture>, ComputeError> {
         let mut set_builder = PersistentDescriptorSet::start(
             self.pipeline
                 .layout()
                 .descriptor_set_layouts()
-                .get(0)
+                .first()
                 .ok_or(ComputeError::NoImageDescriptor)?
                 .clone(),
         );
@@ -470,6 +571,25 @@ impl ComputeProgram {
 
         let set = set_builder.build()?;
 
+        let buffer_set = if buffers.is_empty() {
+            None
+        } else {
+            let mut set_builder = PersistentDescriptorSet::start(
+                self.pipeline
+                    .layout()
+                    .descriptor_set_layouts()
+                    .get(1)
+                    .ok_or(ComputeError::NoBufferDescriptor)?
+                    .clone(),
+            );
+
+            for buffer in buffers {
+                set_builder.add_buffer(buffer.clone())?;
+            }
+
+            Some(set_builder.build()?)
+        };
+
         let mut builder = AutoCommandBufferBuilder::primary(
             self.context.device(),
             self.context.queue().family(),
@@ -483,7 +603,18 @@ impl ComputeProgram {
                 self.pipeline.layout().clone(),
                 0,
                 set,
-            )
+            );
+
+        if let Some(buffer_set) = buffer_set {
+            builder.bind_descriptor_sets(
+                PipelineBindPoint::Compute,
+                self.pipeline.layout().clone(),
+                1,
+                buffer_set,
+            );
+        }
+
+        builder
             .push_constants(self.pipeline.layout().clone(), 0, push_constants)
             .dispatch(dispatch_dimensions)?;
 
@@ -494,3 +625,146 @@ impl ComputeProgram {
         Ok(future.boxed())
     }
 }
+
+#[allow(clippy::enum_variant_names)]
+#[derive(Error, Debug)]
+pub enum TransferError {
+    #[error("Ran out of memory.")]
+    OomError(#[from] OomError),
+    #[error("Failed to allocate staging buffer.")]
+    DeviceMemoryAllocError(#[from] DeviceMemoryAllocError),
+    #[error("Failed to record image copy.")]
+    CopyBufferImageError(#[from] CopyBufferImageError),
+    #[error("Failed to record image clear.")]
+    ClearColorImageError(#[from] ClearColorImageError),
+    #[error("Failed to build transfer command buffer.")]
+    BuildError(#[from] BuildError),
+    #[error("Failed to execute command buffer.")]
+    CommandBufferExecError(#[from] CommandBufferExecError),
+    #[error("Failed to flush transfer.")]
+    FlushError(#[from] FlushError),
+    #[error("Failed to read staging buffer.")]
+    ReadLockError(#[from] ReadLockError),
+}
+
+/// Copies all layers of `image` into host memory and blocks until the copy has finished.
+///
+/// The texels are returned layer by layer, each layer in row-major order. The slices of 3D
+/// images follow each other like layers.
+pub fn download_image<Px>(
+    context: &Context,
+    image: Arc<StorageImage>,
+    before: Box<dyn GpuFuture>,
+) -> Result<Vec<Px>, TransferError>
+where
+    Px: Pixel + Copy + Default + Send + Sync + 'static,
+{
+    let dimensions = image.dimensions();
+    let len =
+        dimensions.width() * dimensions.height() * dimensions.depth() * dimensions.array_layers();
+
+    let buffer = CpuAccessibleBuffer::from_iter(
+        context.device(),
+        BufferUsage::transfer_destination(),
+        true,
+        (0..len).map(|_| Px::default()),
+    )?;
+
+    let mut builder = AutoCommandBufferBuilder::primary(
+        context.device(),
+        context.queue().family(),
+        CommandBufferUsage::OneTimeSubmit,
+    )?;
+
+    builder.copy_image_to_buffer_dimensions(
+        image,
+        buffer.clone(),
+        [0; 3],
+        dimensions.width_height_depth(),
+        0,
+        dimensions.array_layers(),
+        0,
+    )?;
+
+    let commands = builder.build()?;
+
+    before
+        .then_execute(context.queue(), commands)?
+        .then_signal_fence_and_flush()?
+        .wait(None)?;
+
+    let texels = buffer.read()?.to_vec();
+
+    Ok(texels)
+}
+
+/// Fills all layers of `image` with `texels`, laid out as in [`download_image`].
+pub fn upload_image<Px>(
+    context: &Context,
+    texels: Vec<Px>,
+    image: Arc<StorageImage>,
+    before: Box<dyn GpuFuture>,
+) -> Result<Box<dyn GpuFuture>, TransferError>
+where
+    Px: Pixel + Copy + Send + Sync + 'static,
+{
+    let dimensions = image.dimensions();
+
+    let buffer = CpuAccessibleBuffer::from_iter(
+        context.device(),
+        BufferUsage::transfer_source(),
+        false,
+        texels,
+    )?;
+
+    let mut builder = AutoCommandBufferBuilder::primary(
+        context.device(),
+        context.queue().family(),
+        CommandBufferUsage::OneTimeSubmit,
+    )?;
+
+    builder.copy_buffer_to_image_dimensions(
+        buffer,
+        image,
+        [0; 3],
+        dimensions.width_height_depth(),
+        0,
+        dimensions.array_layers(),
+        0,
+    )?;
+
+    let commands = builder.build()?;
+
+    let future = before.then_execute(context.queue(), commands)?;
+
+    Ok(future.boxed())
+}
+
+/// Clears all layers of `images` to zero, with integer zeros for integer formats.
+pub fn clear_images(
+    context: &Context,
+    images: Vec<Arc<StorageImage>>,
+    before: Box<dyn GpuFuture>,
+) -> Result<Box<dyn GpuFuture>, TransferError> {
+    let mut builder = AutoCommandBufferBuilder::primary(
+        context.device(),
+        context.queue().family(),
+        CommandBufferUsage::OneTimeSubmit,
+    )?;
+
+    for image in images {
+        let zero = match image.format().type_color() {
+            Some(NumericType::UINT) => ClearValue::Uint([0; 4]),
+            Some(NumericType::SINT) => ClearValue::Int([0; 4]),
+            _ => ClearValue::Float([0.0; 4]),
+        };
+
+        builder.clear_color_image(image, zero)?;
+    }
+
+    let commands = builder.build()?;
+
+    let future = before.then_execute(context.queue(), commands)?;
+
+    Ok(future.boxed())
+}
@@ -0,0 +1,122 @@
+//! Constants of the D2Q9 lattice shared by the GPU kernel and the CPU reference.
+//!
+//! Everything in here has to stay in sync with `src/shaders/main.comp`.
+
+use serde::Deserialize;
+
+pub const Q: usize = 9;
+
+/// Lattice velocities, in the same order as `c` in the compute shader.
+pub const C: [[i32; 2]; Q] = [
+    [0, 0],
+    [1, 0],
+    [0, 1],
+    [-1, 0],
+    [0, -1],
+    [1, 1],
+    [-1, 1],
+    [1, -1],
+    [-1, -1],
+];
+
+/// Lattice weights.
+pub const W: [f32; Q] = [
+    4.0 / 9.0,
+    1.0 / 9.0,
+    1.0 / 9.0,
+    1.0 / 9.0,
+    1.0 / 9.0,
+    1.0 / 36.0,
+    1.0 / 36.0,
+    1.0 / 36.0,
+    1.0 / 36.0,
+];
+
+/// Index of the opposite lattice velocity, used for bounce-back.
+pub const OPP: [usize; Q] = [0, 3, 4, 1, 2, 8, 7, 6, 5];
+
+/// Velocities of the D2Q5 temperature lattice, the first five entries of `C`.
+pub const Q_THERMAL: usize = 5;
+
+/// Weights of the D2Q5 temperature lattice.
+pub const W_THERMAL: [f32; Q_THERMAL] = [1.0 / 3.0, 1.0 / 6.0, 1.0 / 6.0, 1.0 / 6.0, 1.0 / 6.0];
+
+#[repr(u8)]
+#[derive(Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum CellType {
+    Fluid = 0,
+    Wall = 1,
+    /// A wall held at the hot temperature of the thermal parameters.
+    HotWall = 2,
+    /// A wall held at the cold temperature of the thermal parameters.
+    ColdWall = 3,
+    /// Not simulated, only exerts the gas pressure of the free-surface mode on the liquid.
+    Gas = 4,
+    /// Partially filled cell between fluid and gas in the free-surface mode.
+    Interface = 5,
+    /// Absorbs incoming populations and emits the equilibrium at its density at rest.
+    Sink = 6,
+    /// Absorbs incoming populations and emits the equilibrium at its density and velocity.
+    Source = 7,
+}
+
+impl CellType {
+    pub fn from_u8(value: u8) -> Option<Self> {
+        match value {
+            0 => Some(CellType::Fluid),
+            1 => Some(CellType::Wall),
+            2 => Some(CellType::HotWall),
+            3 => Some(CellType::ColdWall),
+            4 => Some(CellType::Gas),
+            5 => Some(CellType::Interface),
+            6 => Some(CellType::Sink),
+            7 => Some(CellType::Source),
+            _ => None,
+        }
+    }
+
+    /// Whether fluid populations bounce back from the cell.
+    pub fn is_wall(value: u8) -> bool {
+        value == CellType::Wall as u8
+            || value == CellType::HotWall as u8
+            || value == CellType::ColdWall as u8
+    }
+
+    /// Whether the cell is collided and streamed.
+    pub fn is_liquid(value: u8) -> bool {
+        value == CellType::Fluid as u8 || value == CellType::Interface as u8
+    }
+}
+
+/// The product-form entropic equilibrium, evaluated in the same order as `f_eq` in the shader.
+pub fn f_eq(i: usize, rho: f32, p: [f32; 2]) -> f32 {
+    let u = [p[0] / rho, p[1] / rho];
+
+    let sx = (1.0 + 3.0 * u[0] * u[0]).sqrt();
+    let sy = (1.0 + 3.0 * u[1] * u[1]).sqrt();
+
+    W[i] * rho
+        * (2.0 - sx)
+        * (2.0 - sy)
+        * ((2.0 * u[0] + sx) / (1.0 - u[0])).powi(C[i][0])
+        * ((2.0 * u[1] + sy) / (1.0 - u[1])).powi(C[i][1])
+}
+
+/// The second order polynomial equilibrium used by the BGK, TRT, MRT and regularized operators.
+pub fn f_eq_polynomial(i: usize, rho: f32, p: [f32; 2]) -> f32 {
+    let u = [p[0] / rho, p[1] / rho];
+
+    let cu = C[i][0] as f32 * u[0] + C[i][1] as f32 * u[1];
+    let uu = u[0] * u[0] + u[1] * u[1];
+
+    W[i] * rho * (1.0 + 3.0 * cu + 4.5 * cu * cu - 1.5 * uu)
+}
+
+/// Equilibrium of the D2Q5 distributions of a scalar advected with the velocity `u`, i.e. the
+/// temperature or the dye concentration.
+pub fn g_eq(i: usize, temperature: f32, u: [f32; 2]) -> f32 {
+    let cu = C[i][0] as f32 * u[0] + C[i][1] as f32 * u[1];
+
+    W_THERMAL[i] * temperature * (1.0 + 3.0 * cu)
+}
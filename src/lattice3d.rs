@@ -0,0 +1,112 @@
+//! Constants of the D3Q19 and D3Q27 lattices shared by the 3D kernel and its CPU reference.
+//!
+//! The velocities are ordered by length and in opposite pairs, so the D3Q19 velocities are the
+//! first 19 of the D3Q27 ones. Everything in here has to stay in sync with
+//! `src/shaders/main3d.comp`.
+
+use serde::Deserialize;
+
+/// Number of velocities of the larger lattice, the size of all per-cell arrays.
+pub const Q_MAX: usize = 27;
+
+/// Lattice velocities, in the same order as `c` in the 3D compute shader: rest, faces, edges
+/// and corners of the unit cube.
+pub const C: [[i32; 3]; Q_MAX] = [
+    [0, 0, 0],
+    [1, 0, 0],
+    [-1, 0, 0],
+    [0, 1, 0],
+    [0, -1, 0],
+    [0, 0, 1],
+    [0, 0, -1],
+    [1, 1, 0],
+    [-1, -1, 0],
+    [1, -1, 0],
+    [-1, 1, 0],
+    [1, 0, 1],
+    [-1, 0, -1],
+    [1, 0, -1],
+    [-1, 0, 1],
+    [0, 1, 1],
+    [0, -1, -1],
+    [0, 1, -1],
+    [0, -1, 1],
+    [1, 1, 1],
+    [-1, -1, -1],
+    [1, 1, -1],
+    [-1, -1, 1],
+    [1, -1, 1],
+    [-1, 1, -1],
+    [-1, 1, 1],
+    [1, -1, -1],
+];
+
+/// Inward normals of the left, right, top, bottom, front and back faces, in the order the 3D
+/// compute shader indexes them.
+pub const FACE_NORMALS: [[i32; 3]; 6] = [
+    [1, 0, 0],
+    [-1, 0, 0],
+    [0, 1, 0],
+    [0, -1, 0],
+    [0, 0, 1],
+    [0, 0, -1],
+];
+
+#[derive(Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum VelocitySet {
+    #[default]
+    D3Q19,
+    /// Adds the corners of the cube, more isotropic at high Reynolds numbers.
+    D3Q27,
+}
+
+impl VelocitySet {
+    /// Number of velocities, also passed to the compute shader.
+    pub fn q(self) -> usize {
+        match self {
+            VelocitySet::D3Q19 => 19,
+            VelocitySet::D3Q27 => 27,
+        }
+    }
+
+    /// Weight of velocity `i`, see `weight` in the shader.
+    pub fn weight(self, i: usize) -> f32 {
+        match (self, i) {
+            (VelocitySet::D3Q19, 0) => 1.0 / 3.0,
+            (VelocitySet::D3Q19, 1..=6) => 1.0 / 18.0,
+            (VelocitySet::D3Q19, _) => 1.0 / 36.0,
+            (VelocitySet::D3Q27, 0) => 8.0 / 27.0,
+            (VelocitySet::D3Q27, 1..=6) => 2.0 / 27.0,
+            (VelocitySet::D3Q27, 7..=18) => 1.0 / 54.0,
+            (VelocitySet::D3Q27, _) => 1.0 / 216.0,
+        }
+    }
+
+    /// The second order polynomial equilibrium.
+    pub fn f_eq(self, i: usize, rho: f32, p: [f32; 3]) -> f32 {
+        let u = [p[0] / rho, p[1] / rho, p[2] / rho];
+
+        let cu = dot(C[i], u);
+        let uu = u[0] * u[0] + u[1] * u[1] + u[2] * u[2];
+
+        self.weight(i) * rho * (1.0 + 3.0 * cu + 4.5 * cu * cu - 1.5 * uu)
+    }
+}
+
+/// Index of the opposite lattice velocity, used for bounce-back.
+pub fn opp(i: usize) -> usize {
+    if i == 0 {
+        0
+    } else {
+        ((i - 1) ^ 1) + 1
+    }
+}
+
+pub fn dot(c: [i32; 3], v: [f32; 3]) -> f32 {
+    c[0] as f32 * v[0] + c[1] as f32 * v[1] + c[2] as f32 * v[2]
+}
+
+pub fn idot(a: [i32; 3], b: [i32; 3]) -> i32 {
+    a[0] * b[0] + a[1] * b[1] + a[2] * b[2]
+}
@@ -0,0 +1,33 @@
+pub mod bodies;
+pub mod boundary;
+pub mod collision;
+pub mod cpu;
+pub mod cpu3d;
+pub mod dye;
+pub mod fields;
+pub mod fields3d;
+pub mod forces;
+pub mod free_surface;
+pub mod gpu;
+pub mod lattice;
+pub mod lattice3d;
+pub mod mask;
+pub mod multiphase;
+pub mod parameters;
+pub mod rheology;
+pub mod scene;
+pub mod thermal;
+pub mod turbulence;
+pub mod units;
+
+mod simulation;
+mod simulation3d;
+
+pub use bodies::RigidBody;
+pub use fields::{Fields, InitialConditions};
+pub use fields3d::{Fields3d, InitialConditions3d};
+pub use forces::{ForceSample, ObstacleForce};
+pub use parameters::{Parameters, Parameters3d};
+pub use scene::Scene;
+pub use simulation::{LatticeView, Simulation, SimulationCreationError, StepError};
+pub use simulation3d::Simulation3d;
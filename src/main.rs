@@ -1,17 +1,17 @@
-use vulkano::format::Format;
-use vulkano::image::view::ImageView;
-use vulkano::image::{ImageCreateFlags, ImageDimensions, ImageUsage, StorageImage};
-
 use vulkano::sync;
 
+use std::collections::VecDeque;
 use std::sync::Arc;
 use std::time::Instant;
+use vulkano::image::ImageViewAbstract;
 use vulkano::sync::GpuFuture;
 use winit::event::{ElementState, Event, MouseButton, VirtualKeyCode, WindowEvent};
 use winit::event_loop::{ControlFlow, EventLoop};
 use winit::window::{Fullscreen, WindowBuilder};
 
-mod gpu;
+use magma_lbm::forces::{self, FORCE_HISTORY};
+use magma_lbm::scene::SceneError;
+use magma_lbm::{gpu, ForceSample, Scene, Simulation, Simulation3d};
 
 mod vs {
     vulkano_shaders::shader! {
@@ -27,14 +27,14 @@ mod fs {
     }
 }
 
-mod cs {
-    vulkano_shaders::shader! {
-        ty: "compute",
-        path: "src/shaders/main.comp"
-    }
-}
-
 fn main() -> anyhow::Result<()> {
+    let scene_path = std::env::args().nth(1);
+
+    let mut scene = match &scene_path {
+        Some(path) => Scene::load(path)?,
+        None => Scene::default(),
+    };
+
     let event_loop = EventLoop::new();
 
     let mode = event_loop
@@ -54,7 +54,7 @@ fn main() -> anyhow::Result<()> {
 
     let context = gpu::Context::new(&window)?;
 
-    let dims = context.swapchain().dimensions();
+    let dims = context.swapchain().unwrap().dimensions();
 
     println!(
         "GPU in use: {}\n Resolution: {}x{}, Swapchainsize: {}x{}",
@@ -67,7 +67,6 @@ fn main() -> anyhow::Result<()> {
 
     let vertex_shader = vs::Shader::load(context.device()).unwrap();
     let fragment_shader = fs::Shader::load(context.device()).unwrap();
-    let compute_shader = cs::Shader::load(context.device()).unwrap();
 
     let renderer = gpu::Renderer::new(
         &context,
@@ -75,72 +74,16 @@ fn main() -> anyhow::Result<()> {
         fragment_shader.main_entry_point(),
     )?;
 
-    let simulation_size = [dims[0] / 2, dims[1] / 2];
+    let default_size = [dims[0] / 2, dims[1] / 2];
 
-    let storage_images = vec![
-        StorageImage::with_usage(
-            context.device(),
-            ImageDimensions::Dim2d {
-                width: simulation_size[0] as u32,
-                height: simulation_size[1] as u32,
-                array_layers: 9,
-            },
-            Format::R32_SFLOAT,
-            ImageUsage {
-                sampled: true,
-                storage: true,
-                ..ImageUsage::none()
-            },
-            ImageCreateFlags::none(),
-            [context.queue().family()],
-        )?,
-        StorageImage::with_usage(
-            context.device(),
-            ImageDimensions::Dim2d {
-                width: simulation_size[0] as u32,
-                height: simulation_size[1] as u32,
-                array_layers: 9,
-            },
-            Format::R32_SFLOAT,
-            ImageUsage {
-                sampled: true,
-                storage: true,
-                ..ImageUsage::none()
-            },
-            ImageCreateFlags::none(),
-            [context.queue().family()],
-        )?,
-        StorageImage::with_usage(
-            context.device(),
-            ImageDimensions::Dim2d {
-                width: simulation_size[0] as u32,
-                height: simulation_size[1] as u32,
-                array_layers: 1,
-            },
-            Format::R8_UINT,
-            ImageUsage {
-                sampled: true,
-                storage: true,
-                ..ImageUsage::none()
-            },
-            ImageCreateFlags::none(),
-            [context.queue().family()],
-        )?,
-    ];
-
-    let compute_program = gpu::ComputeProgram::new(&context, &compute_shader.main_entry_point())?;
+    let mut solver = Solver::new(&context, &scene, default_size)?;
 
-    let views = storage_images
-        .iter()
-        .cloned()
-        .map(ImageView::new)
-        .collect::<Result<Vec<_>, _>>()?;
-
-    let mut input_view = views[0].clone();
-    let mut output_view = views[1].clone();
+    apply_scene(&mut solver, &scene)?;
 
     let mut last_frame = Instant::now();
 
+    let mut force_history = VecDeque::new();
+
     let mut _last_frame_end = Some(sync::now(context.device()).boxed());
 
     let mut brightness = 1.0;
@@ -149,13 +92,6 @@ fn main() -> anyhow::Result<()> {
     let mut cursor_pos = [0.0, 0.0];
     let mut mouse_pressed = false;
 
-    let mut compute_uniforms = cs::ty::PushConstants {
-        init: 1,
-        mouse_pos: [0.0, 0.0],
-        mouse_delta: [0.0, 0.0],
-        dissipation: 0.0,
-    };
-
     event_loop.run(move |event, _, flow| {
         match event {
             Event::WindowEvent {
@@ -170,11 +106,40 @@ fn main() -> anyhow::Result<()> {
                     Some(VirtualKeyCode::Comma) if input.state == ElementState::Pressed => {
                         brightness *= 0.9;
                     }
+                    Some(VirtualKeyCode::Up) if input.state == ElementState::Pressed => {
+                        solver.move_slice(1);
+                    }
+                    Some(VirtualKeyCode::Down) if input.state == ElementState::Pressed => {
+                        solver.move_slice(-1);
+                    }
                     Some(VirtualKeyCode::Escape) => *flow = ControlFlow::Exit,
-                    Some(VirtualKeyCode::R) => compute_uniforms.init = 1,
+                    Some(VirtualKeyCode::R) if input.state == ElementState::Pressed => {
+                        if let Some(path) = &scene_path {
+                            match Scene::load(path) {
+                                Ok(reloaded) => scene = reloaded,
+                                Err(err) => eprintln!("Failed to reload scene: {}", err),
+                            }
+                        }
+
+                        if !solver.fits(&scene, default_size) {
+                            match Solver::new(&context, &scene, default_size) {
+                                Ok(created) => solver = created,
+                                Err(err) => eprintln!("Failed to create simulation: {}", err),
+                            }
+                        }
+
+                        // The previous simulation keeps running if none fits the scene.
+                        if solver.fits(&scene, default_size) {
+                            if let Err(err) = apply_scene(&mut solver, &scene) {
+                                eprintln!("Failed to apply scene: {}", err);
+                            }
+                        }
+
+                        force_history.clear();
+                    }
                     Some(VirtualKeyCode::Space) => match input.state {
-                        ElementState::Pressed => compute_uniforms.dissipation = 1.0,
-                        ElementState::Released => compute_uniforms.dissipation = 0.0,
+                        ElementState::Pressed => solver.set_dissipation(1.0),
+                        ElementState::Released => solver.set_dissipation(0.0),
                     },
                     _ => {}
                 },
@@ -197,14 +162,16 @@ fn main() -> anyhow::Result<()> {
                 }
 
                 WindowEvent::CursorMoved { position, .. } => {
+                    let size = solver.plane_size();
+
                     let new_mouse_pos = [
-                        position.x as f32 / dims[1] as f32,
+                        position.x as f32 / dims[0] as f32 * size[0] as f32 / size[1] as f32,
                         position.y as f32 / dims[1] as f32,
                     ];
 
                     /*if mouse_pressed {
-                        compute_uniforms.mouse_delta[0] = (new_mouse_pos[0] - mouse_pos[0]) * 60.;
-                        compute_uniforms.mouse_delta[1] = (new_mouse_pos[1] - mouse_pos[1]) * 60.;
+                        mouse_delta[0] = (new_mouse_pos[0] - mouse_pos[0]) * 60.;
+                        mouse_delta[1] = (new_mouse_pos[1] - mouse_pos[1]) * 60.;
                     }*/
 
                     mouse_pos = new_mouse_pos;
@@ -212,41 +179,40 @@ fn main() -> anyhow::Result<()> {
                 _ => {}
             },
             Event::RedrawRequested(_) => {
-                let compute_future = compute_program
-                    .compute(
-                        &[input_view.clone(), output_view.clone(), views[2].clone()],
-                        [simulation_size[0] / 8 + 1, simulation_size[1] / 8 + 1, 1],
-                        compute_uniforms,
-                        sync::now(context.device()).boxed(),
-                    )
-                    .unwrap()
-                    .boxed();
+                let compute_future = solver.step(sync::now(context.device()).boxed()).unwrap();
 
                 let render_future = renderer
                     .draw(
-                        &[output_view.clone(), views[2].clone()],
+                        &solver.views(),
                         compute_future,
                         fs::ty::PushConstants { brightness },
                     )
                     .unwrap()
                     .boxed();
 
-                drop(render_future);
+                if last_frame.elapsed().as_secs_f32() >= 1.0 {
+                    force_history.extend(solver.read_forces(render_future).unwrap());
 
-                std::mem::swap(&mut input_view, &mut &mut output_view);
+                    while force_history.len() > FORCE_HISTORY {
+                        force_history.pop_front();
+                    }
 
-                compute_uniforms.init = 0;
+                    window.set_title(&status(&solver, &scene, &force_history));
+                    last_frame = Instant::now();
+                } else {
+                    drop(render_future);
+                }
 
-                if mouse_pressed {
-                    compute_uniforms.mouse_delta = [
+                let mouse_delta = if mouse_pressed {
+                    [
                         0.5 * (mouse_pos[0] - cursor_pos[0]),
                         0.5 * (mouse_pos[1] - cursor_pos[1]),
-                    ];
+                    ]
                 } else {
-                    compute_uniforms.mouse_delta = [0., 0.];
-                }
+                    [0., 0.]
+                };
 
-                compute_uniforms.mouse_pos = cursor_pos;
+                solver.set_brush(cursor_pos, mouse_delta);
             }
             _ => {}
         }
@@ -254,3 +220,180 @@ fn main() -> anyhow::Result<()> {
         window.request_redraw();
     });
 }
+
+/// The 2D simulation or, if the scene has a volume, the 3D one showing a slice.
+#[allow(clippy::large_enum_variant)]
+enum Solver {
+    Planar(Simulation),
+    Volume(Simulation3d),
+}
+
+impl Solver {
+    fn new(context: &gpu::Context, scene: &Scene, default_size: [u32; 2]) -> anyhow::Result<Self> {
+        Ok(match (&scene.volume, scene.size_3d(default_size)) {
+            (Some(volume), Some(size)) => {
+                Solver::Volume(Simulation3d::new(context, size, volume.velocity_set)?)
+            }
+            _ => Solver::Planar(Simulation::new(context, scene.size(default_size))?),
+        })
+    }
+
+    /// Whether the scene can be applied without recreating the simulation.
+    fn fits(&self, scene: &Scene, default_size: [u32; 2]) -> bool {
+        match (self, &scene.volume) {
+            (Solver::Planar(simulation), None) => simulation.size() == scene.size(default_size),
+            (Solver::Volume(simulation), Some(volume)) => {
+                Some(simulation.size()) == scene.size_3d(default_size)
+                    && simulation.velocity_set() == volume.velocity_set
+            }
+            _ => false,
+        }
+    }
+
+    fn step(&mut self, before: Box<dyn GpuFuture>) -> anyhow::Result<Box<dyn GpuFuture>> {
+        Ok(match self {
+            Solver::Planar(simulation) => simulation.step(before)?,
+            Solver::Volume(simulation) => simulation.step(before)?,
+        })
+    }
+
+    /// Waits for `before` and returns the forces on the obstacles since the last call. The 3D
+    /// simulation doesn't measure forces.
+    fn read_forces(&mut self, before: Box<dyn GpuFuture>) -> anyhow::Result<Vec<ForceSample>> {
+        Ok(match self {
+            Solver::Planar(simulation) => simulation.read_forces(before)?,
+            Solver::Volume(_) => Vec::new(),
+        })
+    }
+
+    /// Images shown by the renderer: distributions, cell types and dye.
+    fn views(&self) -> [Arc<dyn ImageViewAbstract>; 3] {
+        match self {
+            Solver::Planar(simulation) => [
+                simulation.lattice(),
+                simulation.type_mask(),
+                simulation.dye(),
+            ],
+            Solver::Volume(simulation) => [
+                simulation.lattice(),
+                simulation.type_mask(),
+                simulation.dye(),
+            ],
+        }
+    }
+
+    fn plane_size(&self) -> [u32; 2] {
+        match self {
+            Solver::Planar(simulation) => simulation.size(),
+            Solver::Volume(simulation) => [simulation.size()[0], simulation.size()[1]],
+        }
+    }
+
+    fn steps(&self) -> u64 {
+        match self {
+            Solver::Planar(simulation) => simulation.steps(),
+            Solver::Volume(simulation) => simulation.steps(),
+        }
+    }
+
+    /// The brush only acts on the 2D simulation.
+    fn set_brush(&mut self, position: [f32; 2], delta: [f32; 2]) {
+        if let Solver::Planar(simulation) = self {
+            simulation.set_brush(position, delta);
+        }
+    }
+
+    fn set_dissipation(&mut self, dissipation: f32) {
+        if let Solver::Planar(simulation) = self {
+            simulation.set_dissipation(dissipation);
+        }
+    }
+
+    fn move_slice(&mut self, delta: i32) {
+        if let Solver::Volume(simulation) = self {
+            let slice = simulation.slice() as i32 + delta;
+
+            simulation.set_slice(slice.max(0) as u32);
+        }
+    }
+}
+
+fn apply_scene(solver: &mut Solver, scene: &Scene) -> Result<(), SceneError> {
+    if let Some(units) = scene.unit_system()? {
+        println!(
+            "Re = {:.0}, dx = {:.3e} m, dt = {:.3e} s, tau = {:.4}",
+            units.reynolds(),
+            units.dx(),
+            units.dt(),
+            units.tau()
+        );
+
+        for warning in units.warnings() {
+            eprintln!("Warning: {}", warning);
+        }
+    }
+
+    match solver {
+        Solver::Planar(simulation) => {
+            simulation.set_parameters(scene.parameters());
+            simulation.set_initial_conditions(scene.initial_conditions(simulation.size())?);
+        }
+        Solver::Volume(simulation) => {
+            simulation.set_parameters(scene.parameters_3d());
+            simulation.set_initial_conditions(scene.initial_conditions_3d(simulation.size())?);
+        }
+    }
+
+    Ok(())
+}
+
+/// Window title showing the progress of the simulation, in physical time if the scene has units.
+fn status(solver: &Solver, scene: &Scene, force_history: &VecDeque<ForceSample>) -> String {
+    let units = scene.unit_system().ok().flatten();
+
+    let mut status = match units {
+        Some(units) => format!(
+            "magma-lbm | Re = {:.0} | t = {:.4} s | step {}",
+            units.reynolds(),
+            units.physical_time(solver.steps()),
+            solver.steps()
+        ),
+        None => format!("magma-lbm | step {}", solver.steps()),
+    };
+
+    let first_forces: Vec<[f32; 2]> = force_history
+        .iter()
+        .filter_map(|sample| sample.obstacles.first())
+        .map(|obstacle| obstacle.force)
+        .collect();
+
+    // Coefficients of the first obstacle, with the characteristic length and velocity of the
+    // scene and the lattice density of 1.
+    if let (Some(&force), Some(units)) = (first_forces.last(), units) {
+        let velocity = units.lattice_velocity();
+        let length = units.lattice_length();
+        let drag =
+            first_forces.iter().map(|force| force[0]).sum::<f32>() / first_forces.len() as f32;
+        let lift: Vec<f32> = first_forces.iter().map(|force| force[1]).collect();
+
+        status += &format!(
+            " | C_D = {:.3} | C_L = {:.3}",
+            forces::force_coefficient(drag, 1.0, velocity, length),
+            forces::force_coefficient(force[1], 1.0, velocity, length)
+        );
+
+        if let Some(frequency) = forces::dominant_frequency(&lift) {
+            status += &format!(
+                " | St = {:.3}",
+                forces::strouhal_number(frequency, velocity, length)
+            );
+        }
+    } else if let Some(force) = first_forces.last() {
+        status += &format!(" | F = ({:.2e}, {:.2e})", force[0], force[1]);
+    }
+
+    match solver {
+        Solver::Volume(simulation) => format!("{} | slice z = {}", status, simulation.slice()),
+        Solver::Planar(_) => status,
+    }
+}
@@ -0,0 +1,148 @@
+//! Cell type masks drawn in an image editor.
+//!
+//! Every pixel is mapped to the cell type of the closest colour in a palette, so slightly
+//! anti-aliased drawings still produce clean masks.
+
+use std::path::Path;
+
+use image::imageops::{self, FilterType};
+use image::RgbImage;
+use serde::Deserialize;
+use thiserror::Error;
+
+use crate::lattice::CellType;
+
+#[derive(Error, Debug)]
+pub enum MaskError {
+    #[error("Failed to load mask image.")]
+    ImageError(#[from] image::ImageError),
+    #[error("Mask palette is empty.")]
+    EmptyPalette,
+}
+
+#[derive(Deserialize, Clone, Copy, Debug, PartialEq)]
+pub struct PaletteEntry {
+    pub color: [u8; 3],
+    #[serde(rename = "type")]
+    pub cell_type: CellType,
+}
+
+/// White is fluid, black is wall, red is sink and green is source.
+pub fn default_palette() -> Vec<PaletteEntry> {
+    vec![
+        PaletteEntry {
+            color: [255, 255, 255],
+            cell_type: CellType::Fluid,
+        },
+        PaletteEntry {
+            color: [0, 0, 0],
+            cell_type: CellType::Wall,
+        },
+        PaletteEntry {
+            color: [255, 0, 0],
+            cell_type: CellType::Sink,
+        },
+        PaletteEntry {
+            color: [0, 255, 0],
+            cell_type: CellType::Source,
+        },
+    ]
+}
+
+/// Loads a PNG or PGM image and converts it into a row-major type mask of the given size.
+pub fn load_mask(
+    path: impl AsRef<Path>,
+    size: [u32; 2],
+    palette: &[PaletteEntry],
+) -> Result<Vec<u8>, MaskError> {
+    let image = image::open(path)?.to_rgb8();
+
+    mask_from_image(&image, size, palette)
+}
+
+/// Rescales `image` to `size` with nearest neighbour filtering and maps it through `palette`.
+pub fn mask_from_image(
+    image: &RgbImage,
+    size: [u32; 2],
+    palette: &[PaletteEntry],
+) -> Result<Vec<u8>, MaskError> {
+    if palette.is_empty() {
+        return Err(MaskError::EmptyPalette);
+    }
+
+    let resized = imageops::resize(image, size[0], size[1], FilterType::Nearest);
+
+    Ok(resized
+        .pixels()
+        .map(|pixel| closest_entry(pixel.0, palette).cell_type as u8)
+        .collect())
+}
+
+fn closest_entry(color: [u8; 3], palette: &[PaletteEntry]) -> &PaletteEntry {
+    let distance = |entry: &&PaletteEntry| -> i32 {
+        entry
+            .color
+            .iter()
+            .zip(&color)
+            .map(|(&a, &b)| (a as i32 - b as i32).pow(2))
+            .sum()
+    };
+
+    palette.iter().min_by_key(distance).unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use image::Rgb;
+
+    use super::*;
+
+    #[test]
+    fn colours_map_to_the_closest_palette_entry() {
+        let colours = [[250, 250, 245], [12, 3, 20], [200, 40, 30], [30, 220, 10]];
+        let image = RgbImage::from_fn(4, 1, |x, _| Rgb(colours[x as usize]));
+
+        let mask = mask_from_image(&image, [4, 1], &default_palette()).unwrap();
+
+        assert_eq!(
+            mask,
+            [
+                CellType::Fluid as u8,
+                CellType::Wall as u8,
+                CellType::Sink as u8,
+                CellType::Source as u8,
+            ]
+        );
+    }
+
+    #[test]
+    fn image_is_rescaled_to_the_lattice() {
+        // Left half wall, right half fluid.
+        let image = RgbImage::from_fn(2, 2, |x, _| {
+            if x == 0 {
+                Rgb([0, 0, 0])
+            } else {
+                Rgb([255, 255, 255])
+            }
+        });
+
+        let mask = mask_from_image(&image, [6, 3], &default_palette()).unwrap();
+
+        assert_eq!(mask.len(), 18);
+
+        for row in mask.chunks(6) {
+            assert_eq!(row[..3], [CellType::Wall as u8; 3]);
+            assert_eq!(row[3..], [CellType::Fluid as u8; 3]);
+        }
+    }
+
+    #[test]
+    fn empty_palette_is_rejected() {
+        let image = RgbImage::new(1, 1);
+
+        assert!(matches!(
+            mask_from_image(&image, [1, 1], &[]),
+            Err(MaskError::EmptyPalette)
+        ));
+    }
+}
@@ -0,0 +1,90 @@
+use crate::boundary::{Boundaries, Boundaries3d, Boundary};
+use crate::collision::Collision;
+use crate::dye::Dye;
+use crate::free_surface::FreeSurface;
+use crate::multiphase::Multiphase;
+use crate::rheology::Rheology;
+use crate::thermal::Thermal;
+use crate::turbulence::Turbulence;
+
+/// Runtime parameters of the compute kernel, mirrored by the `Parameters` uniform block.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Parameters {
+    /// Relaxation parameter, the shear modes are over-relaxed with `2 * beta`.
+    pub beta: f32,
+    pub collision: Collision,
+    /// Subgrid model adding an eddy viscosity to the one given by `beta`.
+    pub turbulence: Turbulence,
+    /// Shear-rate dependent viscosity replacing the one given by `beta`.
+    pub rheology: Rheology,
+    /// Brush radius in lattice cells.
+    pub brush_size: f32,
+    /// Velocity added per unit of brush stroke.
+    pub brush_strength: f32,
+    /// Uniform acceleration, the force on a cell scales with its density.
+    pub gravity: [f32; 2],
+    /// Uniform force density, e.g. the negative pressure gradient driving a channel flow.
+    pub force: [f32; 2],
+    pub boundaries: Boundaries,
+    /// Temperature field, disabled if `None`.
+    pub thermal: Option<Thermal>,
+    /// Dye advected by the flow, disabled if `None`.
+    pub dye: Option<Dye>,
+    /// Shan–Chen interaction between neighbouring cells.
+    pub multiphase: Multiphase,
+    /// Free-surface mode with gas and interface cells, disabled if `None`.
+    pub free_surface: Option<FreeSurface>,
+}
+
+impl Default for Parameters {
+    fn default() -> Self {
+        Self {
+            beta: 0.99,
+            collision: Collision::default(),
+            turbulence: Turbulence::default(),
+            rheology: Rheology::default(),
+            brush_size: 10.0,
+            brush_strength: 0.01,
+            gravity: [0.0, 0.0],
+            force: [0.0, 0.0],
+            boundaries: Boundaries {
+                left: Boundary::NoSlip,
+                right: Boundary::NoSlip,
+                ..Boundaries::default()
+            },
+            thermal: None,
+            dye: None,
+            multiphase: Multiphase::None,
+            free_surface: None,
+        }
+    }
+}
+
+/// Runtime parameters of the 3D kernel, mirrored by the `Parameters` uniform block of
+/// `main3d.comp`. It always collides with BGK.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Parameters3d {
+    /// Relaxation parameter, the populations are relaxed with `2 * beta`.
+    pub beta: f32,
+    /// Uniform acceleration, the force on a cell scales with its density.
+    pub gravity: [f32; 3],
+    /// Uniform force density.
+    pub force: [f32; 3],
+    pub boundaries: Boundaries3d,
+}
+
+impl Default for Parameters3d {
+    fn default() -> Self {
+        let parameters = Parameters::default();
+
+        Self {
+            beta: parameters.beta,
+            gravity: [0.0; 3],
+            force: [0.0; 3],
+            boundaries: Boundaries3d {
+                sides: parameters.boundaries,
+                ..Boundaries3d::default()
+            },
+        }
+    }
+}
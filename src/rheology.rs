@@ -0,0 +1,181 @@
+//! Generalized Newtonian fluids whose viscosity depends on the local shear rate.
+//!
+//! The shear rate follows from the non-equilibrium stress and the relaxation time, which in
+//! turn follows from the viscosity at that shear rate. Both the shader and the CPU version
+//! solve this with a few fixed-point iterations starting from the relaxation time of `beta`.
+//! All parameters are in lattice units.
+
+use serde::Deserialize;
+
+#[derive(Deserialize, Clone, Copy, Debug, Default, PartialEq)]
+#[serde(tag = "type", rename_all = "kebab-case")]
+pub enum Rheology {
+    /// Constant viscosity given by `beta`.
+    #[default]
+    Newtonian,
+    /// `consistency * shear_rate^(index - 1)`, shear-thinning for an index below 1. The
+    /// viscosity diverges or vanishes at zero shear, so it is clamped to
+    /// `[min_viscosity, max_viscosity]`.
+    PowerLaw {
+        consistency: f32,
+        index: f32,
+        #[serde(default = "default_min_viscosity")]
+        min_viscosity: f32,
+        #[serde(default = "default_max_viscosity")]
+        max_viscosity: f32,
+    },
+    /// Carreau–Yasuda model, e.g. for blood: falls from `zero_viscosity` to
+    /// `infinite_viscosity` around a shear rate of `1 / time`, with the power-law `index` in
+    /// between and the transition sharpness `a`. `a = 2` is the Carreau model.
+    CarreauYasuda {
+        zero_viscosity: f32,
+        infinite_viscosity: f32,
+        time: f32,
+        index: f32,
+        #[serde(default = "default_yasuda_exponent")]
+        a: f32,
+    },
+    /// Bingham plastic regularized after Papanastasiou (1987): flows with
+    /// `plastic_viscosity` above the `yield_stress` and with a viscosity up to
+    /// `regularization * yield_stress / rho` larger below it. The regularization has to be
+    /// large against the inverse shear rate, which is large itself in lattice units.
+    Bingham {
+        plastic_viscosity: f32,
+        yield_stress: f32,
+        #[serde(default = "default_regularization")]
+        regularization: f32,
+    },
+}
+
+fn default_min_viscosity() -> f32 {
+    0.001
+}
+
+fn default_max_viscosity() -> f32 {
+    1.0
+}
+
+fn default_yasuda_exponent() -> f32 {
+    2.0
+}
+
+fn default_regularization() -> f32 {
+    1e5
+}
+
+const ITERATIONS: usize = 8;
+
+/// Keeps the power law finite at rest, the clamping takes over from there.
+const MIN_SHEAR_RATE: f32 = 1e-9;
+
+/// Below this `regularization * shear_rate` the Bingham viscosity is taken at zero shear,
+/// where the exponential loses precision.
+const MIN_REGULARIZED_SHEAR: f32 = 1e-4;
+
+impl Rheology {
+    /// Rheology model constant used by the compute shader.
+    pub fn kind(&self) -> u32 {
+        match self {
+            Rheology::Newtonian => 0,
+            Rheology::PowerLaw { .. } => 1,
+            Rheology::CarreauYasuda { .. } => 2,
+            Rheology::Bingham { .. } => 3,
+        }
+    }
+
+    /// Model parameters as passed to the compute shader.
+    pub fn values(&self) -> [[f32; 4]; 2] {
+        match *self {
+            Rheology::Newtonian => [[0.0; 4]; 2],
+            Rheology::PowerLaw {
+                consistency,
+                index,
+                min_viscosity,
+                max_viscosity,
+            } => [[consistency, index, min_viscosity, max_viscosity], [0.0; 4]],
+            Rheology::CarreauYasuda {
+                zero_viscosity,
+                infinite_viscosity,
+                time,
+                index,
+                a,
+            } => [
+                [zero_viscosity, infinite_viscosity, time, index],
+                [a, 0.0, 0.0, 0.0],
+            ],
+            Rheology::Bingham {
+                plastic_viscosity,
+                yield_stress,
+                regularization,
+            } => [
+                [plastic_viscosity, yield_stress, regularization, 0.0],
+                [0.0; 4],
+            ],
+        }
+    }
+
+    /// Kinematic viscosity at the given shear rate and density, the one of `beta` for a
+    /// Newtonian fluid.
+    pub fn viscosity(&self, beta: f32, shear_rate: f32, rho: f32) -> f32 {
+        match *self {
+            Rheology::Newtonian => (0.5 / beta - 0.5) / 3.0,
+            Rheology::PowerLaw {
+                consistency,
+                index,
+                min_viscosity,
+                max_viscosity,
+            } => (consistency * shear_rate.max(MIN_SHEAR_RATE).powf(index - 1.0))
+                .max(min_viscosity)
+                .min(max_viscosity),
+            Rheology::CarreauYasuda {
+                zero_viscosity,
+                infinite_viscosity,
+                time,
+                index,
+                a,
+            } => {
+                infinite_viscosity
+                    + (zero_viscosity - infinite_viscosity)
+                        * (1.0 + (time * shear_rate).powf(a)).powf((index - 1.0) / a)
+            }
+            Rheology::Bingham {
+                plastic_viscosity,
+                yield_stress,
+                regularization,
+            } => {
+                let x = regularization * shear_rate;
+
+                let yielded = if x < MIN_REGULARIZED_SHEAR {
+                    regularization
+                } else {
+                    (1.0 - (-x).exp()) / shear_rate
+                };
+
+                plastic_viscosity + yield_stress / rho * yielded
+            }
+        }
+    }
+
+    /// Relaxation parameter for the local shear rate, see `effective_beta` in the shader.
+    ///
+    /// `stress` is the non-equilibrium momentum flux `[xx, xy, yy]`.
+    pub fn effective_beta(&self, beta: f32, rho: f32, stress: [f32; 3]) -> f32 {
+        if *self == Rheology::Newtonian {
+            return beta;
+        }
+
+        let q = (2.0
+            * (stress[0] * stress[0] + 2.0 * stress[1] * stress[1] + stress[2] * stress[2]))
+            .sqrt();
+
+        let mut tau = 0.5 / beta;
+
+        for _ in 0..ITERATIONS {
+            let shear_rate = 1.5 * q / (rho * tau);
+
+            tau = 3.0 * self.viscosity(beta, shear_rate, rho) + 0.5;
+        }
+
+        0.5 / tau
+    }
+}
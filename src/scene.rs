@@ -0,0 +1,1026 @@
+//! Declarative scene files describing the lattice, its parameters and its geometry.
+//!
+//! Positions are given in the same units the compute shader uses: lattice coordinates divided
+//! by the lattice height, so `y` runs from 0 at the top to 1 at the bottom.
+
+use std::path::{Path, PathBuf};
+
+use serde::Deserialize;
+use thiserror::Error;
+
+use crate::bodies::{Outline, RigidBody, MAX_BODY_VERTICES};
+use crate::boundary::{Boundaries, Boundaries3d, Boundary, Edge};
+use crate::collision::Collision;
+use crate::dye::Dye;
+use crate::fields::InitialConditions;
+use crate::fields3d::InitialConditions3d;
+use crate::forces::MAX_OBSTACLES;
+use crate::free_surface::FreeSurface;
+use crate::lattice::{CellType, C, Q};
+use crate::lattice3d::VelocitySet;
+use crate::mask::{self, MaskError, PaletteEntry};
+use crate::multiphase::Multiphase;
+use crate::parameters::{Parameters, Parameters3d};
+use crate::rheology::Rheology;
+use crate::thermal::Thermal;
+use crate::turbulence::Turbulence;
+use crate::units::{PhysicalSetup, UnitError, UnitSystem};
+
+#[derive(Error, Debug)]
+pub enum SceneError {
+    #[error("Failed to read scene file.")]
+    IoError(#[from] std::io::Error),
+    #[error("Failed to parse scene file: {0}")]
+    ParseError(#[from] toml::de::Error),
+    #[error("Failed to load mask: {0}")]
+    MaskError(#[from] MaskError),
+    #[error("Invalid units: {0}")]
+    UnitError(#[from] UnitError),
+    #[error("Moving obstacles can have at most {} vertices.", MAX_BODY_VERTICES)]
+    TooManyVertices,
+    #[error("The mass of a moving obstacle has to be positive, got {0}.")]
+    InvalidMass(f32),
+}
+
+/// A scene file starts from an empty domain with periodic edges: everything it leaves out takes
+/// the default of its own type, not of [`Scene::default`], the built-in cylinder setup.
+#[derive(Deserialize, Clone, Debug)]
+pub struct Scene {
+    #[serde(default)]
+    pub lattice: LatticeConfig,
+    #[serde(default)]
+    pub boundaries: Boundaries,
+    #[serde(default)]
+    pub brush: BrushConfig,
+    #[serde(default)]
+    pub initial: InitialFlow,
+    #[serde(default)]
+    pub forcing: Forcing,
+    pub mask: Option<MaskConfig>,
+    /// Physical description of the setup. If given, it determines the lattice size and
+    /// `beta`, and all velocities in the scene are in m/s.
+    pub units: Option<PhysicalSetup>,
+    /// Temperature field, its diffusivity is in m²/s and its buoyancy in m/s² per unit of
+    /// temperature if the scene has units.
+    pub thermal: Option<Thermal>,
+    /// Dye advected by the flow, its diffusivity is in m²/s if the scene has units.
+    pub dye: Option<Dye>,
+    #[serde(default)]
+    pub multiphase: Multiphase,
+    /// Free-surface mode, cells with a fill level of zero are gas.
+    pub free_surface: Option<FreeSurface>,
+    /// Forces are measured on the first [`MAX_OBSTACLES`] obstacles, in this order. Only these
+    /// can move.
+    #[serde(default)]
+    pub obstacles: Vec<Obstacle>,
+    /// Walls held at the hot temperature of the thermal parameters.
+    #[serde(default)]
+    pub hot_walls: Vec<Shape>,
+    /// Walls held at the cold temperature of the thermal parameters.
+    #[serde(default)]
+    pub cold_walls: Vec<Shape>,
+    #[serde(default)]
+    pub inlets: Vec<Inlet>,
+    #[serde(default)]
+    pub outlets: Vec<Outlet>,
+    #[serde(default)]
+    pub forces: Vec<ForceRegion>,
+    #[serde(default)]
+    pub phases: Vec<PhaseRegion>,
+    /// Extrudes the scene along z into a 3D lattice.
+    pub volume: Option<Volume>,
+}
+
+#[derive(Deserialize, Clone, Debug)]
+#[serde(default)]
+pub struct LatticeConfig {
+    /// Lattice size in cells, half the window size if not given.
+    pub size: Option<[u32; 2]>,
+    pub beta: f32,
+    pub collision: Collision,
+    pub turbulence: Turbulence,
+    pub rheology: Rheology,
+    /// Places the walls of obstacles at their actual surface instead of halfway between wall
+    /// and fluid cells, using interpolated bounce-back. Moving obstacles stay halfway.
+    pub curved_walls: bool,
+}
+
+/// A mask image providing the base cell types, obstacles and edges are drawn on top of it.
+#[derive(Deserialize, Clone, Debug)]
+pub struct MaskConfig {
+    /// Relative paths are resolved against the directory of the scene file.
+    pub path: PathBuf,
+    #[serde(default = "mask::default_palette")]
+    pub palette: Vec<PaletteEntry>,
+}
+
+#[derive(Deserialize, Clone, Debug)]
+#[serde(default)]
+pub struct BrushConfig {
+    pub size: f32,
+    pub strength: f32,
+}
+
+#[derive(Deserialize, Clone, Debug)]
+#[serde(default)]
+pub struct InitialFlow {
+    pub density: f32,
+    pub velocity: [f32; 2],
+    /// Only used if the scene has a temperature field.
+    pub temperature: f32,
+    /// Only used by the two-component multiphase model.
+    pub second_density: f32,
+    /// Only used in the free-surface mode.
+    pub fill: f32,
+}
+
+/// Uniform body forces, see `Parameters::gravity` and `Parameters::force`.
+#[derive(Deserialize, Clone, Debug, Default)]
+#[serde(default)]
+pub struct Forcing {
+    /// Acceleration, in m/s² if the scene has units.
+    pub gravity: [f32; 2],
+    /// Force density, in N/m³ if the scene has units. A pressure gradient driving the flow is
+    /// given as its negative.
+    pub force: [f32; 2],
+}
+
+#[derive(Deserialize, Clone, Debug)]
+#[serde(tag = "shape", rename_all = "lowercase")]
+pub enum Shape {
+    Circle { center: [f32; 2], radius: f32 },
+    Rectangle { min: [f32; 2], max: [f32; 2] },
+    Polygon { points: Vec<[f32; 2]> },
+}
+
+/// A wall that may slide and rotate in place, dragging the fluid along. With a mass it is a
+/// rigid body moving under the forces of the flow and gravity instead, starting with the given
+/// velocities.
+#[derive(Deserialize, Clone, Debug)]
+pub struct Obstacle {
+    #[serde(flatten)]
+    pub shape: Shape,
+    /// Velocity of the surface, in m/s if the scene has units.
+    #[serde(default)]
+    pub velocity: [f32; 2],
+    /// Angular velocity about the center of the shape, in rad per step or in rad/s if the
+    /// scene has units. Positive values turn from `+x` towards `+y`, clockwise on screen.
+    #[serde(default)]
+    pub angular_velocity: f32,
+    /// Mass per unit depth, in kg/m if the scene has units and in lattice units otherwise, where
+    /// a cell of fluid has a mass of about one. Bodies rotate about their centroid.
+    pub mass: Option<f32>,
+    /// Position of a hinge the body turns about instead of moving freely.
+    pub pivot: Option<[f32; 2]>,
+}
+
+/// A region of source cells injecting fluid at the given velocity and density.
+#[derive(Deserialize, Clone, Debug)]
+pub struct Inlet {
+    #[serde(flatten)]
+    pub shape: Shape,
+    pub velocity: [f32; 2],
+    #[serde(default = "default_density")]
+    pub density: f32,
+    /// Temperature of the injected fluid, the initial temperature if not given.
+    pub temperature: Option<f32>,
+    /// Dye concentration of the injected fluid.
+    #[serde(default)]
+    pub dye: f32,
+}
+
+/// A region of cells with an additional force density, in N/m³ if the scene has units.
+#[derive(Deserialize, Clone, Debug)]
+pub struct ForceRegion {
+    #[serde(flatten)]
+    pub shape: Shape,
+    pub force: [f32; 2],
+}
+
+/// A region initially filled with fluid of other densities than the initial flow, e.g. a
+/// droplet or a bubble. In the free-surface mode also the liquid in a gas-filled domain.
+#[derive(Deserialize, Clone, Debug)]
+pub struct PhaseRegion {
+    #[serde(flatten)]
+    pub shape: Shape,
+    pub density: f32,
+    #[serde(default)]
+    pub second_density: f32,
+    #[serde(default = "default_fill")]
+    pub fill: f32,
+}
+
+/// A region of sink cells holding the adjacent fluid at the given density.
+#[derive(Deserialize, Clone, Debug)]
+pub struct Outlet {
+    #[serde(flatten)]
+    pub shape: Shape,
+    #[serde(default = "default_density")]
+    pub density: f32,
+}
+
+/// The 3D mode, see [`Simulation3d`](crate::Simulation3d). Every cell of the 2D scene becomes a
+/// column of `depth` cells. Only the flow, the walls, inlets and outlets carry over.
+#[derive(Deserialize, Clone, Debug)]
+pub struct Volume {
+    #[serde(default)]
+    pub velocity_set: VelocitySet,
+    /// Number of cells along z.
+    pub depth: u32,
+    /// Boundary at `z == 0`.
+    #[serde(default)]
+    pub front: Boundary,
+    /// Boundary at `z == depth - 1`.
+    #[serde(default)]
+    pub back: Boundary,
+    #[serde(default)]
+    pub spheres: Vec<Sphere>,
+}
+
+/// A spherical wall, its center is given in units of the lattice height like all positions,
+/// with `z` running from the front face.
+#[derive(Deserialize, Clone, Debug)]
+pub struct Sphere {
+    pub center: [f32; 3],
+    pub radius: f32,
+}
+
+fn default_density() -> f32 {
+    1.0
+}
+
+fn default_fill() -> f32 {
+    1.0
+}
+
+impl Default for LatticeConfig {
+    fn default() -> Self {
+        Self {
+            size: None,
+            beta: Parameters::default().beta,
+            collision: Collision::default(),
+            turbulence: Turbulence::default(),
+            rheology: Rheology::default(),
+            curved_walls: false,
+        }
+    }
+}
+
+impl Default for BrushConfig {
+    fn default() -> Self {
+        let parameters = Parameters::default();
+
+        Self {
+            size: parameters.brush_size,
+            strength: parameters.brush_strength,
+        }
+    }
+}
+
+impl Default for InitialFlow {
+    fn default() -> Self {
+        Self {
+            density: 1.0,
+            velocity: [0.0, 0.0],
+            temperature: 0.0,
+            second_density: 0.0,
+            fill: 1.0,
+        }
+    }
+}
+
+/// The setup built into the compute shader: a cylinder in a channel that is periodic
+/// from top to bottom.
+impl Default for Scene {
+    fn default() -> Self {
+        Self {
+            lattice: LatticeConfig::default(),
+            boundaries: Parameters::default().boundaries,
+            brush: BrushConfig::default(),
+            initial: InitialFlow::default(),
+            forcing: Forcing::default(),
+            mask: None,
+            units: None,
+            thermal: None,
+            dye: None,
+            multiphase: Multiphase::None,
+            free_surface: None,
+            obstacles: vec![Obstacle {
+                shape: Shape::Circle {
+                    center: [0.5, 0.5],
+                    radius: 0.05,
+                },
+                velocity: [0.0, 0.0],
+                angular_velocity: 0.0,
+                mass: None,
+                pivot: None,
+            }],
+            hot_walls: Vec::new(),
+            cold_walls: Vec::new(),
+            inlets: Vec::new(),
+            outlets: Vec::new(),
+            forces: Vec::new(),
+            phases: Vec::new(),
+            volume: None,
+        }
+    }
+}
+
+impl Scene {
+    pub fn load(path: impl AsRef<Path>) -> Result<Self, SceneError> {
+        let source = std::fs::read_to_string(&path)?;
+
+        let mut scene = Self::parse(&source)?;
+
+        if let (Some(mask), Some(directory)) = (&mut scene.mask, path.as_ref().parent()) {
+            mask.path = directory.join(&mask.path);
+        }
+
+        Ok(scene)
+    }
+
+    pub fn parse(source: &str) -> Result<Self, SceneError> {
+        let scene: Self = toml::from_str(source)?;
+
+        // Validate the physical setup up front, later conversions rely on it.
+        scene.unit_system()?;
+
+        Ok(scene)
+    }
+
+    /// The unit system of the physical setup, if the scene has one.
+    pub fn unit_system(&self) -> Result<Option<UnitSystem>, UnitError> {
+        self.units.as_ref().map(UnitSystem::new).transpose()
+    }
+
+    /// The lattice size, falling back to `default` if the scene doesn't specify one. A
+    /// physical setup takes precedence over `lattice.size`.
+    pub fn size(&self, default: [u32; 2]) -> [u32; 2] {
+        match self.unit_system() {
+            Ok(Some(units)) => units.size(),
+            _ => self.lattice.size.unwrap_or(default),
+        }
+    }
+
+    /// The lattice size of the 3D mode, see [`Scene::size`].
+    pub fn size_3d(&self, default: [u32; 2]) -> Option<[u32; 3]> {
+        let [width, height] = self.size(default);
+
+        self.volume
+            .as_ref()
+            .map(|volume| [width, height, volume.depth])
+    }
+
+    pub fn parameters(&self) -> Parameters {
+        let units = self.unit_system().ok().flatten();
+
+        Parameters {
+            beta: units.map_or(self.lattice.beta, |units| units.beta()),
+            collision: self.lattice.collision,
+            turbulence: self.lattice.turbulence,
+            rheology: self.lattice.rheology,
+            brush_size: self.brush.size,
+            brush_strength: self.brush.strength,
+            gravity: units.map_or(self.forcing.gravity, |units| {
+                units.to_lattice_acceleration(self.forcing.gravity)
+            }),
+            force: to_lattice_force(units, self.forcing.force),
+            boundaries: self
+                .boundaries
+                .map_velocities(|velocity| to_lattice_velocity(units, velocity)),
+            thermal: self.thermal.map(|thermal| match units {
+                Some(units) => Thermal {
+                    diffusivity: units.to_lattice_diffusivity(thermal.diffusivity),
+                    buoyancy: units.to_lattice_acceleration(thermal.buoyancy),
+                    ..thermal
+                },
+                None => thermal,
+            }),
+            dye: self.dye.map(|dye| Dye {
+                diffusivity: units.map_or(dye.diffusivity, |units| {
+                    units.to_lattice_diffusivity(dye.diffusivity)
+                }),
+                ..dye
+            }),
+            multiphase: self.multiphase,
+            free_surface: self.free_surface,
+        }
+    }
+
+    /// Parameters of the 3D mode, the scene has to have a volume. Forces act in the xy plane.
+    pub fn parameters_3d(&self) -> Parameters3d {
+        let units = self.unit_system().ok().flatten();
+        let parameters = self.parameters();
+        let volume = self.volume.as_ref().expect("scene has no volume");
+
+        let convert = |velocity| to_lattice_velocity(units, velocity);
+
+        Parameters3d {
+            beta: parameters.beta,
+            gravity: [parameters.gravity[0], parameters.gravity[1], 0.0],
+            force: [parameters.force[0], parameters.force[1], 0.0],
+            boundaries: Boundaries3d {
+                sides: parameters.boundaries,
+                front: volume.front.map_velocity(convert),
+                back: volume.back.map_velocity(convert),
+            },
+        }
+    }
+
+    /// Extrudes the initial conditions of the 2D scene along z and adds the spheres.
+    pub fn initial_conditions_3d(&self, size: [u32; 3]) -> Result<InitialConditions3d, SceneError> {
+        let volume = self.volume.as_ref().expect("scene has no volume");
+        let planar = self.initial_conditions([size[0], size[1]])?;
+
+        let mut initial_conditions = InitialConditions3d::new(size);
+
+        let [width, height, depth] = size;
+
+        for z in 0..depth {
+            for y in 0..height {
+                for x in 0..width {
+                    let index = initial_conditions.index(x, y, z);
+                    let source = planar.index(x, y);
+                    let pos = [
+                        x as f32 / height as f32,
+                        y as f32 / height as f32,
+                        z as f32 / height as f32,
+                    ];
+
+                    let [u, v] = planar.velocity[source];
+
+                    initial_conditions.density[index] = planar.density[source];
+                    initial_conditions.velocity[index] = [u, v, 0.0];
+                    initial_conditions.type_mask[index] = planar.type_mask[source];
+
+                    if volume.spheres.iter().any(|sphere| sphere.contains(pos)) {
+                        initial_conditions.velocity[index] = [0.0; 3];
+                        initial_conditions.type_mask[index] = CellType::Wall as u8;
+                    }
+                }
+            }
+        }
+
+        Ok(initial_conditions)
+    }
+
+    /// Rasterizes the geometry and the initial flow onto a lattice of the given size.
+    pub fn initial_conditions(&self, size: [u32; 2]) -> Result<InitialConditions, SceneError> {
+        let units = self.unit_system()?;
+
+        let mut initial_conditions = InitialConditions::new(size);
+
+        if let Some(mask) = &self.mask {
+            initial_conditions.type_mask = mask::load_mask(&mask.path, size, &mask.palette)?;
+        }
+
+        let [width, height] = size;
+
+        for y in 0..height {
+            for x in 0..width {
+                let index = initial_conditions.index(x, y);
+                let pos = [x as f32 / height as f32, y as f32 / height as f32];
+
+                let mut density = self.initial.density;
+                let mut velocity = self.initial.velocity;
+                let mut temperature = self.initial.temperature;
+                let mut dye = 0.0;
+                let mut wall_velocity = [0.0, 0.0];
+                let mut obstacle = 0;
+                let mut second_density = self.initial.second_density;
+                let mut fill = self.initial.fill;
+
+                if let Some(phase) = self.phases.iter().find(|phase| phase.shape.contains(pos)) {
+                    density = phase.density;
+                    second_density = phase.second_density;
+                    fill = phase.fill;
+                }
+
+                let mut cell_type = CellType::from_u8(initial_conditions.type_mask[index])
+                    .unwrap_or(CellType::Fluid);
+
+                if let Some(inlet) = self.inlets.iter().find(|inlet| inlet.shape.contains(pos)) {
+                    density = inlet.density;
+                    velocity = inlet.velocity;
+                    temperature = inlet.temperature.unwrap_or(temperature);
+                    dye = inlet.dye;
+                    cell_type = CellType::Source;
+                }
+
+                if let Some(outlet) = self
+                    .outlets
+                    .iter()
+                    .find(|outlet| outlet.shape.contains(pos))
+                {
+                    density = outlet.density;
+                    velocity = [0.0, 0.0];
+                    cell_type = CellType::Sink;
+                }
+
+                if let Some(index) = self
+                    .obstacles
+                    .iter()
+                    .position(|obstacle| obstacle.shape.contains(pos))
+                {
+                    wall_velocity = self.obstacles[index].wall_velocity(pos, height, units);
+                    cell_type = CellType::Wall;
+
+                    if index < MAX_OBSTACLES {
+                        obstacle = index as u8 + 1;
+                    }
+                }
+
+                if self.hot_walls.iter().any(|shape| shape.contains(pos)) {
+                    cell_type = CellType::HotWall;
+                    obstacle = 0;
+                }
+
+                if self.cold_walls.iter().any(|shape| shape.contains(pos)) {
+                    cell_type = CellType::ColdWall;
+                    obstacle = 0;
+                }
+
+                initial_conditions.density[index] = density;
+                // Wall cells keep the velocity of their surface.
+                initial_conditions.velocity[index] = if CellType::is_wall(cell_type as u8) {
+                    wall_velocity
+                } else {
+                    to_lattice_velocity(units, velocity)
+                };
+                initial_conditions.temperature[index] = temperature;
+                initial_conditions.dye[index] = dye;
+                initial_conditions.second_density[index] = second_density;
+                initial_conditions.fill[index] = fill;
+
+                for region in self
+                    .forces
+                    .iter()
+                    .filter(|region| region.shape.contains(pos))
+                {
+                    let force = to_lattice_force(units, region.force);
+
+                    initial_conditions.force[index][0] += force[0];
+                    initial_conditions.force[index][1] += force[1];
+                }
+
+                initial_conditions.type_mask[index] = cell_type as u8;
+                initial_conditions.obstacle[index] = obstacle;
+            }
+        }
+
+        if self.free_surface.is_some() {
+            initial_conditions.close_interface();
+        }
+
+        if self.lattice.curved_walls {
+            self.find_wall_distances(&mut initial_conditions);
+        }
+
+        initial_conditions.bodies = self.bodies(height, units)?;
+
+        Ok(initial_conditions)
+    }
+
+    /// The obstacles with a mass as rigid bodies on a lattice of the given height.
+    fn bodies(&self, height: u32, units: Option<UnitSystem>) -> Result<Vec<RigidBody>, SceneError> {
+        let scale = |point: &[f32; 2]| [point[0] * height as f32, point[1] * height as f32];
+
+        let mut bodies = Vec::new();
+
+        for (index, obstacle) in self.obstacles.iter().enumerate().take(MAX_OBSTACLES) {
+            let mass = match obstacle.mass {
+                Some(mass) if mass > 0.0 => units.map_or(mass, |units| units.to_lattice_mass(mass)),
+                Some(mass) => return Err(SceneError::InvalidMass(mass)),
+                None => continue,
+            };
+
+            let (position, outline) = match &obstacle.shape {
+                Shape::Circle { center, radius } => (
+                    scale(center),
+                    Outline::Circle {
+                        radius: radius * height as f32,
+                    },
+                ),
+                Shape::Rectangle { min, max } => Outline::polygon(&[
+                    scale(min),
+                    scale(&[max[0], min[1]]),
+                    scale(max),
+                    scale(&[min[0], max[1]]),
+                ]),
+                Shape::Polygon { points } if points.len() > MAX_BODY_VERTICES => {
+                    return Err(SceneError::TooManyVertices);
+                }
+                Shape::Polygon { points } => {
+                    Outline::polygon(&points.iter().map(scale).collect::<Vec<_>>())
+                }
+            };
+
+            let mut body = RigidBody::new(outline, position, mass, index);
+
+            body.velocity = to_lattice_velocity(units, obstacle.velocity);
+            body.angular_velocity = units.map_or(obstacle.angular_velocity, |units| {
+                obstacle.angular_velocity * units.dt()
+            });
+            body.pivot = obstacle.pivot.as_ref().map(scale);
+
+            bodies.push(body);
+        }
+
+        Ok(bodies)
+    }
+
+    /// Intersects the links from non-wall cells to obstacle cells with the obstacle surfaces.
+    /// Links across periodic edges are intersected with the shapes as they extend beyond the
+    /// edge, links across the other edges keep halfway bounce-back.
+    fn find_wall_distances(&self, initial_conditions: &mut InitialConditions) {
+        let [width, height] = initial_conditions.size;
+        let [width, height] = [width as i32, height as i32];
+
+        let periodic = |edge| self.boundaries.get(edge) == Boundary::Periodic;
+
+        for y in 0..height {
+            for x in 0..width {
+                let index = initial_conditions.index(x as u32, y as u32);
+
+                if CellType::is_wall(initial_conditions.type_mask[index]) {
+                    continue;
+                }
+
+                let from = [x as f32 / height as f32, y as f32 / height as f32];
+
+                for (i, c) in C.iter().enumerate().take(Q).skip(1) {
+                    let (nx, ny) = (x + c[0], y + c[1]);
+
+                    let crosses = (nx < 0 && !periodic(Edge::Left))
+                        || (nx >= width && !periodic(Edge::Right))
+                        || (ny < 0 && !periodic(Edge::Top))
+                        || (ny >= height && !periodic(Edge::Bottom));
+
+                    if crosses {
+                        continue;
+                    }
+
+                    let neighbour = initial_conditions.index(
+                        ((nx + width) % width) as u32,
+                        ((ny + height) % height) as u32,
+                    );
+
+                    if initial_conditions.type_mask[neighbour] != CellType::Wall as u8 {
+                        continue;
+                    }
+
+                    let to = [nx as f32 / height as f32, ny as f32 / height as f32];
+
+                    let q = self
+                        .obstacles
+                        .iter()
+                        .filter(|obstacle| obstacle.mass.is_none())
+                        .filter_map(|obstacle| obstacle.shape.intersection(from, to))
+                        .fold(f32::INFINITY, f32::min);
+
+                    if q.is_finite() {
+                        initial_conditions.wall_distance[index][i] = q.max(f32::EPSILON);
+                    }
+                }
+            }
+        }
+    }
+}
+
+impl Shape {
+    /// Center of a circle or rectangle, the mean of the points of a polygon.
+    pub fn center(&self) -> [f32; 2] {
+        match self {
+            Shape::Circle { center, .. } => *center,
+            Shape::Rectangle { min, max } => [0.5 * (min[0] + max[0]), 0.5 * (min[1] + max[1])],
+            Shape::Polygon { points } => {
+                let sum = points.iter().fold([0.0, 0.0], |sum, point| {
+                    [sum[0] + point[0], sum[1] + point[1]]
+                });
+
+                [sum[0] / points.len() as f32, sum[1] / points.len() as f32]
+            }
+        }
+    }
+
+    /// Fraction of the segment from `from` to `to` at which it first crosses the outline of
+    /// the shape, if it does.
+    pub fn intersection(&self, from: [f32; 2], to: [f32; 2]) -> Option<f32> {
+        let d = [to[0] - from[0], to[1] - from[1]];
+
+        match self {
+            Shape::Circle { center, radius } => {
+                let m = [from[0] - center[0], from[1] - center[1]];
+
+                let a = d[0] * d[0] + d[1] * d[1];
+                let b = m[0] * d[0] + m[1] * d[1];
+                let c = m[0] * m[0] + m[1] * m[1] - radius * radius;
+
+                let discriminant = b * b - a * c;
+
+                if discriminant < 0.0 {
+                    return None;
+                }
+
+                // The entry point if `from` lies outside, the exit point otherwise.
+                let sqrt = discriminant.sqrt();
+
+                [(-b - sqrt) / a, (-b + sqrt) / a]
+                    .iter()
+                    .copied()
+                    .find(|t| (0.0..=1.0).contains(t))
+            }
+            Shape::Rectangle { min, max } => Shape::Polygon {
+                points: vec![*min, [max[0], min[1]], *max, [min[0], max[1]]],
+            }
+            .intersection(from, to),
+            Shape::Polygon { points } => {
+                let mut first: Option<f32> = None;
+
+                for (i, a) in points.iter().enumerate() {
+                    let b = points[(i + 1) % points.len()];
+                    let e = [b[0] - a[0], b[1] - a[1]];
+
+                    let denominator = d[0] * e[1] - d[1] * e[0];
+
+                    if denominator == 0.0 {
+                        continue;
+                    }
+
+                    let m = [a[0] - from[0], a[1] - from[1]];
+
+                    let t = (m[0] * e[1] - m[1] * e[0]) / denominator;
+                    let s = (m[0] * d[1] - m[1] * d[0]) / denominator;
+
+                    if (0.0..=1.0).contains(&t) && (0.0..=1.0).contains(&s) {
+                        first = Some(first.map_or(t, |first| first.min(t)));
+                    }
+                }
+
+                first
+            }
+        }
+    }
+
+    pub fn contains(&self, point: [f32; 2]) -> bool {
+        match self {
+            Shape::Circle { center, radius } => {
+                let d = [point[0] - center[0], point[1] - center[1]];
+
+                (d[0] * d[0] + d[1] * d[1]).sqrt() < *radius
+            }
+            Shape::Rectangle { min, max } => {
+                (min[0]..max[0]).contains(&point[0]) && (min[1]..max[1]).contains(&point[1])
+            }
+            Shape::Polygon { points } => {
+                // Even-odd rule: count the edges crossed by a ray going in +x direction.
+                let mut inside = false;
+
+                for (i, a) in points.iter().enumerate() {
+                    let b = points[(i + 1) % points.len()];
+
+                    if (a[1] > point[1]) != (b[1] > point[1]) {
+                        let x = a[0] + (point[1] - a[1]) / (b[1] - a[1]) * (b[0] - a[0]);
+
+                        if point[0] < x {
+                            inside = !inside;
+                        }
+                    }
+                }
+
+                inside
+            }
+        }
+    }
+}
+
+impl Obstacle {
+    /// Velocity of the surface at `pos` in lattice units, on a lattice of the given height.
+    pub fn wall_velocity(&self, pos: [f32; 2], height: u32, units: Option<UnitSystem>) -> [f32; 2] {
+        let center = self.shape.center();
+        let r = [
+            (pos[0] - center[0]) * height as f32,
+            (pos[1] - center[1]) * height as f32,
+        ];
+
+        let omega = units.map_or(self.angular_velocity, |units| {
+            self.angular_velocity * units.dt()
+        });
+        let velocity = to_lattice_velocity(units, self.velocity);
+
+        [velocity[0] - omega * r[1], velocity[1] + omega * r[0]]
+    }
+}
+
+impl Sphere {
+    pub fn contains(&self, point: [f32; 3]) -> bool {
+        let d = [
+            point[0] - self.center[0],
+            point[1] - self.center[1],
+            point[2] - self.center[2],
+        ];
+
+        (d[0] * d[0] + d[1] * d[1] + d[2] * d[2]).sqrt() < self.radius
+    }
+}
+
+/// Converts a velocity given in the scene to lattice units.
+fn to_lattice_velocity(units: Option<UnitSystem>, velocity: [f32; 2]) -> [f32; 2] {
+    units.map_or(velocity, |units| units.to_lattice_velocity(velocity))
+}
+
+/// Converts a force density given in the scene to lattice units.
+fn to_lattice_force(units: Option<UnitSystem>, force: [f32; 2]) -> [f32; 2] {
+    units.map_or(force, |units| units.to_lattice_force_density(force))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SCENES: [&str; 8] = [
+        include_str!("../scenes/cavity.toml"),
+        include_str!("../scenes/cylinder.toml"),
+        include_str!("../scenes/dam_break.toml"),
+        include_str!("../scenes/droplet.toml"),
+        include_str!("../scenes/karman.toml"),
+        include_str!("../scenes/rayleigh_benard.toml"),
+        include_str!("../scenes/smoke.toml"),
+        include_str!("../scenes/sphere.toml"),
+    ];
+
+    #[test]
+    fn bundled_scenes_parse() {
+        for source in SCENES {
+            let scene = Scene::parse(source).unwrap();
+            let size = scene.size([64, 32]);
+
+            scene.initial_conditions(size).unwrap();
+        }
+    }
+
+    #[test]
+    fn empty_scene_file_is_an_empty_periodic_domain() {
+        let scene = Scene::parse("").unwrap();
+
+        assert_eq!(scene.size([64, 32]), [64, 32]);
+        assert_eq!(scene.lattice.beta, Parameters::default().beta);
+        assert_eq!(scene.boundaries.kinds(), [Boundary::Periodic.kind(); 4]);
+        assert!(scene.obstacles.is_empty());
+        assert!(scene.volume.is_none());
+
+        // Unlike the built-in setup.
+        assert_ne!(scene.boundaries, Scene::default().boundaries);
+        assert!(!Scene::default().obstacles.is_empty());
+    }
+
+    #[test]
+    fn obstacles_are_rasterized_as_walls() {
+        let scene = Scene::parse(
+            r#"
+            [lattice]
+            size = [40, 20]
+
+            [[obstacles]]
+            shape = "rectangle"
+            min = [0.5, 0.25]
+            max = [1.0, 0.75]
+            "#,
+        )
+        .unwrap();
+
+        let initial_conditions = scene.initial_conditions(scene.size([0, 0])).unwrap();
+
+        let cell_type = |x, y| initial_conditions.type_mask[initial_conditions.index(x, y)];
+
+        assert_eq!(cell_type(15, 10), CellType::Wall as u8);
+        assert_eq!(
+            initial_conditions.obstacle[initial_conditions.index(15, 10)],
+            1
+        );
+        assert_eq!(cell_type(5, 10), CellType::Fluid as u8);
+        assert_eq!(cell_type(15, 2), CellType::Fluid as u8);
+    }
+
+    #[test]
+    fn curved_walls_lie_on_the_circle() {
+        // A circle of radius 10.5 cells about the cell (40, 20).
+        let scene = Scene::parse(
+            r#"
+            [lattice]
+            size = [80, 40]
+            curved_walls = true
+
+            [[obstacles]]
+            shape = "circle"
+            center = [1.0, 0.5]
+            radius = 0.2625
+            "#,
+        )
+        .unwrap();
+
+        let initial_conditions = scene.initial_conditions(scene.size([0, 0])).unwrap();
+
+        // The link from (29, 20) to the wall cell (30, 20) crosses the circle halfway.
+        let east = C.iter().position(|c| *c == [1, 0]).unwrap();
+        let q = initial_conditions.wall_distance[initial_conditions.index(29, 20)][east];
+
+        assert!((q - 0.5).abs() < 1e-4, "q = {}", q);
+
+        let mut links = 0;
+
+        for y in 0..40 {
+            for x in 0..80 {
+                let index = initial_conditions.index(x, y);
+
+                for (i, c) in C.iter().enumerate().take(Q).skip(1) {
+                    let q = initial_conditions.wall_distance[index][i];
+
+                    if q == 0.0 {
+                        continue;
+                    }
+
+                    assert!(q > 0.0 && q <= 1.0);
+
+                    let point = [x as f32 + q * c[0] as f32, y as f32 + q * c[1] as f32];
+                    let distance = (point[0] - 40.0).hypot(point[1] - 20.0);
+
+                    assert!((distance - 10.5).abs() < 1e-3, "distance = {}", distance);
+
+                    links += 1;
+                }
+            }
+        }
+
+        assert!(links > 0);
+    }
+
+    fn falling_circle(mass: f32) -> Scene {
+        Scene::parse(&format!(
+            r#"
+            [lattice]
+            size = [40, 20]
+
+            [[obstacles]]
+            shape = "circle"
+            center = [1.0, 0.5]
+            radius = 0.25
+            velocity = [0.01, 0.0]
+            mass = {:?}
+            "#,
+            mass
+        ))
+        .unwrap()
+    }
+
+    #[test]
+    fn obstacles_with_a_mass_become_bodies() {
+        let initial_conditions = falling_circle(50.0).initial_conditions([40, 20]).unwrap();
+
+        let body = &initial_conditions.bodies[0];
+
+        assert_eq!(initial_conditions.bodies.len(), 1);
+        assert_eq!(body.obstacle, 0);
+        assert_eq!(body.position, [20.0, 10.0]);
+        assert_eq!(body.velocity, [0.01, 0.0]);
+        assert_eq!(body.mass, 50.0);
+    }
+
+    #[test]
+    fn bodies_need_a_positive_mass() {
+        for mass in [0.0, -1.0] {
+            assert!(matches!(
+                falling_circle(mass).initial_conditions([40, 20]),
+                Err(SceneError::InvalidMass(invalid)) if invalid == mass
+            ));
+        }
+    }
+
+    #[test]
+    fn invalid_scenes_are_rejected() {
+        assert!(matches!(
+            Scene::parse("[lattice]\nsize = 64"),
+            Err(SceneError::ParseError(_))
+        ));
+
+        assert!(matches!(
+            Scene::parse("[[obstacles]]\nshape = \"triangle\""),
+            Err(SceneError::ParseError(_))
+        ));
+
+        // The physical setup is validated when parsing.
+        assert!(matches!(
+            Scene::parse(
+                r#"
+                [units]
+                reynolds = 100.0
+                viscosity = 1e-6
+                length = 0.1
+                velocity = 1.0
+                domain = [1.0, 0.5]
+                "#
+            ),
+            Err(SceneError::UnitError(UnitError::ViscosityOrReynolds))
+        ));
+    }
+}
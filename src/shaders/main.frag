@@ -6,6 +6,8 @@ layout(push_constant) uniform PushConstants {
 
 layout(set=0, binding=0) uniform sampler2DArray tex;
 layout(set=0, binding=1) uniform usampler2D type;
+// D2Q5 dye distributions, all zero if the simulation has no dye.
+layout(set=0, binding=2) uniform sampler2DArray dye;
 
 layout(location = 0) in vec2 uv;
 
@@ -90,7 +92,10 @@ void main() {
     vec2 p = vec2(0);
     float P = 0;
 
-    if(texture(type, uv).r == 0) {
+    uint cell_type = texture(type, uv).r;
+
+    // Interface cells of the free surface are drawn like fluid.
+    if(cell_type == 0 || cell_type == 5) {
 
         for(int i = 0; i < 9; i++) {
             f[i] = texture(tex, vec3(uv,i)).r;
@@ -105,7 +110,25 @@ void main() {
 
         vec3 rgb = hsl2rgb(vec3(0.0 + v * 0.2, 1.0, v));
 
+        float concentration = 0;
+
+        for(int i = 0; i < 5; i++) {
+            concentration += texture(dye, vec3(uv,i)).r;
+        }
+
+        // Smoke-like dye on top of the velocity magnitude.
+        rgb = mix(rgb, vec3(0.9, 0.92, 1.0), clamp(concentration, 0.0, 1.0));
+
         f_color = vec4(vec3(rgb), 1.0);
+    } else if(cell_type == 4) {
+        // Gas
+        f_color = vec4(0.02,0.02,0.03,1.0);
+    } else if(cell_type == 2) {
+        // Hot wall
+        f_color = vec4(0.35,0.1,0.1,1.0);
+    } else if(cell_type == 3) {
+        // Cold wall
+        f_color = vec4(0.1,0.1,0.35,1.0);
     } else {
 
         f_color = vec4(0.1,0.1,0.1,1.0);
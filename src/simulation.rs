@@ -0,0 +1,921 @@
+use std::sync::Arc;
+
+use thiserror::Error;
+use vulkano::buffer::{BufferAccess, BufferUsage, CpuAccessibleBuffer, CpuBufferPool};
+use vulkano::format::Format;
+use vulkano::image::view::{ImageView, ImageViewCreationError};
+use vulkano::image::{
+    ImageCreateFlags, ImageCreationError, ImageDimensions, ImageUsage, ImageViewAbstract,
+    StorageImage,
+};
+use vulkano::memory::DeviceMemoryAllocError;
+use vulkano::sync::{self, GpuFuture};
+use vulkano::OomError;
+
+use crate::bodies::RigidBody;
+use crate::fields::{Fields, InitialConditions};
+use crate::forces::{self, ForceSample, ObstacleForce, FORCE_HISTORY, MAX_OBSTACLES};
+use crate::gpu::{
+    self, ComputeError, ComputeProgram, ComputeProgramCreationError, Context, TransferError,
+};
+use crate::lattice::Q_THERMAL;
+use crate::parameters::Parameters;
+use crate::rheology::Rheology;
+use crate::turbulence::Turbulence;
+
+mod cs {
+    vulkano_shaders::shader! {
+        ty: "compute",
+        path: "src/shaders/main.comp"
+    }
+}
+
+pub type LatticeView = Arc<ImageView<Arc<StorageImage>>>;
+
+/// Passes after collide-and-stream updating the cell types in the free-surface mode, see
+/// `update_surface` in the shader.
+const FREE_SURFACE_PASSES: u32 = 4;
+
+/// Pass before collide-and-stream rasterizing the moving bodies, see `move_bodies` in the
+/// shader.
+const MOVE_BODIES: u32 = 5;
+
+#[allow(clippy::enum_variant_names)]
+#[derive(Error, Debug)]
+pub enum SimulationCreationError {
+    #[error("Failed to load compute shader.")]
+    ShaderLoadError(#[from] OomError),
+    #[error("Failed to create lattice image.")]
+    ImageCreationError(#[from] ImageCreationError),
+    #[error("Failed to create lattice image view.")]
+    ImageViewCreationError(#[from] ImageViewCreationError),
+    #[error("Failed to create compute program.")]
+    ComputeProgramCreationError(#[from] ComputeProgramCreationError),
+    #[error("Failed to allocate force buffer.")]
+    DeviceMemoryAllocError(#[from] DeviceMemoryAllocError),
+    #[error("The 3D lattice needs images of {0:?} texels with its slices stacked, the device supports at most {1} along each axis.")]
+    VolumeTooLarge([u32; 2], u32),
+    #[error("Failed to clear lattice images.")]
+    TransferError(#[from] TransferError),
+}
+
+#[derive(Error, Debug)]
+pub enum StepError {
+    #[error("Failed to upload initial conditions.")]
+    TransferError(#[from] TransferError),
+    #[error("Failed to run compute program.")]
+    ComputeError(#[from] ComputeError),
+    #[error("Failed to allocate parameter buffer.")]
+    DeviceMemoryAllocError(#[from] DeviceMemoryAllocError),
+    #[error("Failed to allocate the images of a feature.")]
+    FeatureAllocationError(#[from] SimulationCreationError),
+}
+
+/// Optional parts of the model, each with images that are only allocated while it is enabled.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+struct Features {
+    thermal: bool,
+    dye: bool,
+    second_component: bool,
+    free_surface: bool,
+    curved_walls: bool,
+    obstacles: bool,
+}
+
+/// A D2Q9 lattice living on the GPU.
+///
+/// The distributions are stored in two 9-layer images which are swapped after every step,
+/// the cell types live in a separate single-layer mask. The D2Q5 temperature and dye
+/// distributions are swapped along with them in two 5-layer images each, as are the
+/// distributions of the second component of the two-component multiphase model. In the
+/// free-surface mode the mass, density and velocity of the liquid cells are swapped along in
+/// two further images, and the type mask changes every step as the interface moves. Links to
+/// curved walls keep the fraction in front of the wall in another 9-layer image.
+///
+/// The images of optional features are only allocated at the full size while the feature is
+/// enabled, 1×1 dummies cleared to zero take their place otherwise. A feature enabled while
+/// running starts from zero, e.g. with a temperature of zero everywhere.
+///
+/// Every step sums the forces on the obstacles into a ring buffer holding the last
+/// [`FORCE_HISTORY`] steps, which [`Simulation::read_forces`] reads back. Moving bodies are
+/// advanced on the host with the forces of the previous step, so every step waits for it then.
+pub struct Simulation {
+    context: Context,
+    program: ComputeProgram,
+    size: [u32; 2],
+    lattice: [LatticeView; 2],
+    temperature: [LatticeView; 2],
+    dye: [LatticeView; 2],
+    second: [LatticeView; 2],
+    surface: [LatticeView; 2],
+    excess_mass: LatticeView,
+    type_mask: LatticeView,
+    cell_data: LatticeView,
+    force_field: LatticeView,
+    wall_distance: LatticeView,
+    obstacle_mask: LatticeView,
+    /// The features whose images are currently allocated at the full size.
+    features: Features,
+    /// Whether the initial conditions have curved walls, fixed at the last reset.
+    curved_walls: bool,
+    forces: Arc<CpuAccessibleBuffer<[u32]>>,
+    obstacle_centers: [[f32; 2]; MAX_OBSTACLES],
+    obstacles: usize,
+    /// Steps recorded into the force buffer since the simulation was created, the ones before
+    /// `forces_read` have been read back.
+    forces_recorded: u64,
+    forces_read: u64,
+    /// Step number of the sample in each slot of the force buffer.
+    force_steps: Vec<u64>,
+    bodies: Vec<RigidBody>,
+    body_pool: CpuBufferPool<[f32; 4]>,
+    current: usize,
+    steps: u64,
+    initial_conditions: Option<InitialConditions>,
+    parameters: Parameters,
+    parameter_pool: CpuBufferPool<cs::ty::Parameters>,
+    push_constants: cs::ty::PushConstants,
+}
+
+impl Simulation {
+    pub fn new(context: &Context, size: [u32; 2]) -> Result<Self, SimulationCreationError> {
+        let shader = cs::Shader::load(context.device())?;
+        let program = ComputeProgram::new(context, &shader.main_entry_point())?;
+
+        let lattice = [
+            create_image(context, size, 9, Format::R32_SFLOAT)?,
+            create_image(context, size, 9, Format::R32_SFLOAT)?,
+        ];
+
+        let type_mask = create_image(context, size, 1, Format::R8_UINT)?;
+        let cell_data = create_image(context, size, 1, Format::R32G32B32A32_SFLOAT)?;
+        let force_field = create_image(context, size, 1, Format::R32G32B32A32_SFLOAT)?;
+
+        // All features start out disabled.
+        let dummy = |layers, format| create_image(context, [1, 1], layers, format);
+
+        let temperature = [
+            dummy(Q_THERMAL as u32, Format::R32_SFLOAT)?,
+            dummy(Q_THERMAL as u32, Format::R32_SFLOAT)?,
+        ];
+
+        let dye = [
+            dummy(Q_THERMAL as u32, Format::R32_SFLOAT)?,
+            dummy(Q_THERMAL as u32, Format::R32_SFLOAT)?,
+        ];
+
+        let second = [dummy(9, Format::R32_SFLOAT)?, dummy(9, Format::R32_SFLOAT)?];
+
+        let surface = [
+            dummy(1, Format::R32G32B32A32_SFLOAT)?,
+            dummy(1, Format::R32G32B32A32_SFLOAT)?,
+        ];
+
+        let excess_mass = dummy(1, Format::R32_SFLOAT)?;
+        let wall_distance = dummy(9, Format::R32_SFLOAT)?;
+        let obstacle_mask = dummy(1, Format::R8_UINT)?;
+
+        // The renderer samples the dye whether or not there is any.
+        let dummies = [
+            &temperature[0],
+            &temperature[1],
+            &dye[0],
+            &dye[1],
+            &second[0],
+            &second[1],
+            &surface[0],
+            &surface[1],
+            &excess_mass,
+            &wall_distance,
+            &obstacle_mask,
+        ];
+
+        gpu::clear_images(
+            context,
+            dummies.iter().map(|view| storage_image(view)).collect(),
+            sync::now(context.device()).boxed(),
+        )?
+        .then_signal_fence_and_flush()
+        .map_err(TransferError::from)?
+        .wait(None)
+        .map_err(TransferError::from)?;
+
+        let forces = CpuAccessibleBuffer::from_iter(
+            context.device(),
+            BufferUsage {
+                storage_buffer: true,
+                ..BufferUsage::none()
+            },
+            true,
+            (0..FORCE_HISTORY * MAX_OBSTACLES * 4).map(|_| 0u32),
+        )?;
+
+        Ok(Self {
+            context: context.clone(),
+            program,
+            size,
+            lattice,
+            temperature,
+            dye,
+            second,
+            surface,
+            excess_mass,
+            type_mask,
+            cell_data,
+            force_field,
+            wall_distance,
+            obstacle_mask,
+            features: Features::default(),
+            curved_walls: false,
+            forces,
+            obstacle_centers: [[0.0; 2]; MAX_OBSTACLES],
+            obstacles: 0,
+            forces_recorded: 0,
+            forces_read: 0,
+            force_steps: vec![0; FORCE_HISTORY],
+            bodies: Vec::new(),
+            body_pool: CpuBufferPool::new(
+                context.device(),
+                BufferUsage {
+                    storage_buffer: true,
+                    ..BufferUsage::none()
+                },
+            ),
+            current: 0,
+            steps: 0,
+            initial_conditions: None,
+            parameters: Parameters::default(),
+            parameter_pool: CpuBufferPool::uniform_buffer(context.device()),
+            push_constants: cs::ty::PushConstants {
+                init: 1,
+                mouse_pos: [0.0, 0.0],
+                mouse_delta: [0.0, 0.0],
+                dissipation: 0.0,
+                phase: 0,
+            },
+        })
+    }
+
+    /// Records one collide-and-stream step after `before` and swaps the lattice images. In the
+    /// free-surface mode it is followed by the passes updating the interface.
+    pub fn step(&mut self, before: Box<dyn GpuFuture>) -> Result<Box<dyn GpuFuture>, StepError> {
+        let mut before = before;
+
+        let init = self.push_constants.init != 0;
+
+        if init {
+            if let Some(initial_conditions) = &self.initial_conditions {
+                let (centers, obstacles) =
+                    forces::centroids(&initial_conditions.obstacle, self.size);
+
+                self.obstacle_centers = centers;
+                self.obstacles = obstacles;
+                self.bodies = initial_conditions.bodies.clone();
+                self.curved_walls = initial_conditions
+                    .wall_distance
+                    .iter()
+                    .any(|q| q.iter().any(|&q| q > 0.0));
+            } else {
+                // The cylinder of the built-in setup.
+                let center = 0.5 * self.size[1] as f32;
+
+                self.obstacle_centers = [[0.0; 2]; MAX_OBSTACLES];
+                self.obstacle_centers[0] = [center, center];
+                self.obstacles = 1;
+                self.bodies.clear();
+                self.curved_walls = false;
+            }
+        } else if !self.bodies.is_empty() && self.steps > 0 {
+            before = self.advance_bodies(before)?;
+        }
+
+        let features = self.required_features();
+
+        if features != self.features {
+            before = self.allocate(features, before)?;
+        }
+
+        if init {
+            if let Some(initial_conditions) = &self.initial_conditions {
+                before = self.upload(initial_conditions, before)?;
+                self.push_constants.init = 0;
+            }
+        }
+
+        // Moments on bodies are taken about their center of mass.
+        for body in &self.bodies {
+            self.obstacle_centers[body.obstacle] = body.position;
+        }
+
+        let force_slot = (self.forces_recorded % FORCE_HISTORY as u64) as usize;
+
+        let mut obstacle_centers = [[0.0; 4]; MAX_OBSTACLES];
+
+        for (center, &[x, y]) in obstacle_centers.iter_mut().zip(&self.obstacle_centers) {
+            *center = [x, y, 0.0, 0.0];
+        }
+
+        let input = self.lattice[self.current].clone();
+        let output = self.lattice[1 - self.current].clone();
+
+        let thermal = self.parameters.thermal.unwrap_or_default();
+        let dye = self.parameters.dye.unwrap_or_default();
+        let free_surface = self.parameters.free_surface.unwrap_or_default();
+
+        let parameters = Arc::new(self.parameter_pool.next(cs::ty::Parameters {
+            edge_values: self.parameters.boundaries.values(),
+            edge_types: self.parameters.boundaries.kinds(),
+            collision_values: self.parameters.collision.values(),
+            rheology_values: self.parameters.rheology.values(),
+            obstacle_centers,
+            gravity: self.parameters.gravity,
+            force: self.parameters.force,
+            buoyancy: thermal.buoyancy,
+            collision_type: self.parameters.collision.kind(),
+            turbulence_type: self.parameters.turbulence.kind(),
+            turbulence_constant: self.parameters.turbulence.constant(),
+            rheology_type: self.parameters.rheology.kind(),
+            beta: self.parameters.beta,
+            brush_size: self.parameters.brush_size,
+            brush_strength: self.parameters.brush_strength,
+            thermal: self.parameters.thermal.is_some() as u32,
+            thermal_omega: thermal.omega(),
+            reference_temperature: thermal.reference_temperature,
+            hot_temperature: thermal.hot_temperature,
+            cold_temperature: thermal.cold_temperature,
+            dye: self.parameters.dye.is_some() as u32,
+            dye_omega: dye.omega(),
+            dye_brush: dye.brush,
+            multiphase_type: self.parameters.multiphase.kind(),
+            interaction_strength: self.parameters.multiphase.values()[0],
+            wall_interaction: self.parameters.multiphase.values()[1],
+            free_surface: self.parameters.free_surface.is_some() as u32,
+            gas_density: free_surface.gas_density,
+            force_slot: force_slot as u32,
+            bodies: self.bodies.len() as u32,
+            curved_walls: features.curved_walls as u32,
+            obstacles: features.obstacles as u32,
+        })?);
+
+        // The shader needs a buffer to bind even without bodies.
+        let body_values: Vec<[f32; 4]> = if self.bodies.is_empty() {
+            vec![[0.0; 4]]
+        } else {
+            self.bodies.iter().flat_map(|body| body.pack()).collect()
+        };
+
+        let buffers: [Arc<dyn BufferAccess>; 3] = [
+            parameters,
+            self.forces.clone(),
+            Arc::new(self.body_pool.chunk(body_values)?),
+        ];
+
+        let images: Vec<Arc<dyn ImageViewAbstract>> = vec![
+            input,
+            output,
+            self.type_mask.clone(),
+            self.cell_data.clone(),
+            self.force_field.clone(),
+            self.temperature[self.current].clone(),
+            self.temperature[1 - self.current].clone(),
+            self.dye[self.current].clone(),
+            self.dye[1 - self.current].clone(),
+            self.second[self.current].clone(),
+            self.second[1 - self.current].clone(),
+            self.surface[self.current].clone(),
+            self.surface[1 - self.current].clone(),
+            self.excess_mass.clone(),
+            self.wall_distance.clone(),
+            self.obstacle_mask.clone(),
+        ];
+
+        let dispatch = [self.size[0] / 8 + 1, self.size[1] / 8 + 1, 1];
+
+        let mut future = before;
+
+        if !self.bodies.is_empty() && self.push_constants.init == 0 {
+            let push_constants = cs::ty::PushConstants {
+                phase: MOVE_BODIES,
+                ..self.push_constants
+            };
+
+            future = self.program.compute_with_buffers(
+                &images,
+                &buffers,
+                dispatch,
+                push_constants,
+                future,
+            )?;
+        }
+
+        future = self.program.compute_with_buffers(
+            &images,
+            &buffers,
+            dispatch,
+            self.push_constants,
+            future,
+        )?;
+
+        if self.parameters.free_surface.is_some() {
+            for phase in 1..=FREE_SURFACE_PASSES {
+                let push_constants = cs::ty::PushConstants {
+                    phase,
+                    ..self.push_constants
+                };
+
+                future = self.program.compute_with_buffers(
+                    &images,
+                    &buffers,
+                    dispatch,
+                    push_constants,
+                    future,
+                )?;
+            }
+        }
+
+        self.current = 1 - self.current;
+        self.steps += 1;
+        self.push_constants.init = 0;
+
+        self.force_steps[force_slot] = self.steps;
+        self.forces_recorded += 1;
+
+        Ok(future)
+    }
+
+    /// Reinitializes the lattice and the type mask on the next step.
+    ///
+    /// Uses the uploaded initial conditions if there are any, the built-in setup of the
+    /// compute shader otherwise.
+    pub fn reset(&mut self) {
+        self.push_constants.init = 1;
+        self.steps = 0;
+    }
+
+    /// Number of steps recorded since the last reset.
+    pub fn steps(&self) -> u64 {
+        self.steps
+    }
+
+    /// Replaces the built-in setup of the compute shader and resets the simulation.
+    pub fn set_initial_conditions(&mut self, initial_conditions: InitialConditions) {
+        assert_eq!(initial_conditions.size, self.size);
+
+        self.initial_conditions = Some(initial_conditions);
+        self.reset();
+    }
+
+    /// Goes back to the built-in setup of the compute shader on the next reset.
+    pub fn clear_initial_conditions(&mut self) {
+        self.initial_conditions = None;
+    }
+
+    fn upload(
+        &self,
+        initial_conditions: &InitialConditions,
+        before: Box<dyn GpuFuture>,
+    ) -> Result<Box<dyn GpuFuture>, TransferError> {
+        let future = gpu::upload_image(
+            &self.context,
+            initial_conditions.distributions(),
+            storage_image(&self.lattice()),
+            before,
+        )?;
+
+        let mut future = gpu::upload_image(
+            &self.context,
+            initial_conditions.type_mask.clone(),
+            storage_image(&self.type_mask),
+            future,
+        )?;
+
+        future = gpu::upload_image(
+            &self.context,
+            initial_conditions.cell_data(),
+            storage_image(&self.cell_data),
+            future,
+        )?;
+
+        future = gpu::upload_image(
+            &self.context,
+            initial_conditions.force_field(),
+            storage_image(&self.force_field),
+            future,
+        )?;
+
+        // The images of disabled features are dummies.
+        if self.features.thermal {
+            future = gpu::upload_image(
+                &self.context,
+                initial_conditions.thermal_distributions(),
+                storage_image(&self.temperature[self.current]),
+                future,
+            )?;
+        }
+
+        if self.features.dye {
+            future = gpu::upload_image(
+                &self.context,
+                initial_conditions.dye_distributions(),
+                storage_image(&self.dye[self.current]),
+                future,
+            )?;
+        }
+
+        if self.features.second_component {
+            future = gpu::upload_image(
+                &self.context,
+                initial_conditions.second_distributions(),
+                storage_image(&self.second[self.current]),
+                future,
+            )?;
+        }
+
+        if self.features.free_surface {
+            future = gpu::upload_image(
+                &self.context,
+                initial_conditions.surface(),
+                storage_image(&self.surface[self.current]),
+                future,
+            )?;
+
+            future = gpu::upload_image(
+                &self.context,
+                vec![0.0f32; initial_conditions.density.len()],
+                storage_image(&self.excess_mass),
+                future,
+            )?;
+        }
+
+        if self.features.curved_walls {
+            future = gpu::upload_image(
+                &self.context,
+                initial_conditions.wall_distances(),
+                storage_image(&self.wall_distance),
+                future,
+            )?;
+        }
+
+        if self.features.obstacles {
+            future = gpu::upload_image(
+                &self.context,
+                initial_conditions.obstacle.clone(),
+                storage_image(&self.obstacle_mask),
+                future,
+            )?;
+        }
+
+        Ok(future)
+    }
+
+    /// The features the next step needs images for.
+    fn required_features(&self) -> Features {
+        Features {
+            thermal: self.parameters.thermal.is_some(),
+            dye: self.parameters.dye.is_some(),
+            second_component: self.parameters.multiphase.has_second_component(),
+            free_surface: self.parameters.free_surface.is_some(),
+            curved_walls: self.curved_walls,
+            obstacles: self.obstacles > 0 || !self.bodies.is_empty(),
+        }
+    }
+
+    /// Allocates full-size images for the features enabled in `features` and replaces the ones
+    /// of disabled features by dummies. The new images are cleared to zero after `before`.
+    fn allocate(
+        &mut self,
+        features: Features,
+        before: Box<dyn GpuFuture>,
+    ) -> Result<Box<dyn GpuFuture>, StepError> {
+        let context = self.context.clone();
+        let size = self.size;
+        let mut created = Vec::new();
+
+        let mut image = |enabled: bool, layers: u32, format| {
+            let image = create_image(
+                &context,
+                if enabled { size } else { [1, 1] },
+                layers,
+                format,
+            )?;
+
+            created.push(storage_image(&image));
+
+            Ok::<_, SimulationCreationError>(image)
+        };
+
+        if features.thermal != self.features.thermal {
+            self.temperature = [
+                image(features.thermal, Q_THERMAL as u32, Format::R32_SFLOAT)?,
+                image(features.thermal, Q_THERMAL as u32, Format::R32_SFLOAT)?,
+            ];
+        }
+
+        if features.dye != self.features.dye {
+            self.dye = [
+                image(features.dye, Q_THERMAL as u32, Format::R32_SFLOAT)?,
+                image(features.dye, Q_THERMAL as u32, Format::R32_SFLOAT)?,
+            ];
+        }
+
+        if features.second_component != self.features.second_component {
+            self.second = [
+                image(features.second_component, 9, Format::R32_SFLOAT)?,
+                image(features.second_component, 9, Format::R32_SFLOAT)?,
+            ];
+        }
+
+        if features.free_surface != self.features.free_surface {
+            self.surface = [
+                image(features.free_surface, 1, Format::R32G32B32A32_SFLOAT)?,
+                image(features.free_surface, 1, Format::R32G32B32A32_SFLOAT)?,
+            ];
+            self.excess_mass = image(features.free_surface, 1, Format::R32_SFLOAT)?;
+        }
+
+        if features.curved_walls != self.features.curved_walls {
+            self.wall_distance = image(features.curved_walls, 9, Format::R32_SFLOAT)?;
+        }
+
+        if features.obstacles != self.features.obstacles {
+            self.obstacle_mask = image(features.obstacles, 1, Format::R8_UINT)?;
+        }
+
+        self.features = features;
+
+        Ok(gpu::clear_images(&self.context, created, before)?)
+    }
+
+    pub fn size(&self) -> [u32; 2] {
+        self.size
+    }
+
+    pub fn parameters(&self) -> Parameters {
+        self.parameters
+    }
+
+    /// Takes effect with the next step.
+    pub fn set_parameters(&mut self, parameters: Parameters) {
+        self.parameters = parameters;
+    }
+
+    /// Switches the subgrid model, e.g. to change the Smagorinsky constant while running.
+    pub fn set_turbulence(&mut self, turbulence: Turbulence) {
+        self.parameters.turbulence = turbulence;
+    }
+
+    /// Switches the viscosity model, e.g. to fit the parameters of a fluid while running.
+    pub fn set_rheology(&mut self, rheology: Rheology) {
+        self.parameters.rheology = rheology;
+    }
+
+    /// Sets the brush position and stroke, both normalized by the lattice height.
+    pub fn set_brush(&mut self, position: [f32; 2], delta: [f32; 2]) {
+        self.push_constants.mouse_pos = position;
+        self.push_constants.mouse_delta = delta;
+    }
+
+    pub fn set_dissipation(&mut self, dissipation: f32) {
+        self.push_constants.dissipation = dissipation;
+    }
+
+    /// The distributions written by the most recent step.
+    pub fn lattice(&self) -> LatticeView {
+        self.lattice[self.current].clone()
+    }
+
+    /// The temperature distributions written by the most recent step, a 1×1 dummy if the
+    /// simulation has no heat transport.
+    pub fn temperature(&self) -> LatticeView {
+        self.temperature[self.current].clone()
+    }
+
+    /// The dye distributions written by the most recent step, a 1×1 dummy of zeros if the
+    /// simulation has no dye.
+    pub fn dye(&self) -> LatticeView {
+        self.dye[self.current].clone()
+    }
+
+    /// The distributions of the second component written by the most recent step, a 1×1 dummy
+    /// if there is no second component.
+    pub fn second_lattice(&self) -> LatticeView {
+        self.second[self.current].clone()
+    }
+
+    /// The free-surface state written by the most recent step: mass, density and velocity. A 1×1
+    /// dummy without a free surface.
+    pub fn surface(&self) -> LatticeView {
+        self.surface[self.current].clone()
+    }
+
+    pub fn type_mask(&self) -> LatticeView {
+        self.type_mask.clone()
+    }
+
+    /// Downloads the distributions written by the most recent step, stored as `[i][y][x]`.
+    pub fn read_distributions(
+        &self,
+        before: Box<dyn GpuFuture>,
+    ) -> Result<Vec<f32>, TransferError> {
+        gpu::download_image(&self.context, storage_image(&self.lattice()), before)
+    }
+
+    /// Downloads the temperature distributions written by the most recent step, stored as
+    /// `[i][y][x]`.
+    pub fn read_thermal_distributions(
+        &self,
+        before: Box<dyn GpuFuture>,
+    ) -> Result<Vec<f32>, TransferError> {
+        gpu::download_image(&self.context, storage_image(&self.temperature()), before)
+    }
+
+    /// Downloads the dye distributions written by the most recent step, stored as `[i][y][x]`.
+    pub fn read_dye_distributions(
+        &self,
+        before: Box<dyn GpuFuture>,
+    ) -> Result<Vec<f32>, TransferError> {
+        gpu::download_image(&self.context, storage_image(&self.dye()), before)
+    }
+
+    /// Downloads the distributions of the second component written by the most recent step,
+    /// stored as `[i][y][x]`.
+    pub fn read_second_distributions(
+        &self,
+        before: Box<dyn GpuFuture>,
+    ) -> Result<Vec<f32>, TransferError> {
+        gpu::download_image(&self.context, storage_image(&self.second_lattice()), before)
+    }
+
+    /// Downloads the free-surface state written by the most recent step, in the layout of
+    /// [`InitialConditions::surface`].
+    pub fn read_surface(&self, before: Box<dyn GpuFuture>) -> Result<Vec<[f32; 4]>, TransferError> {
+        gpu::download_image(&self.context, storage_image(&self.surface()), before)
+    }
+
+    pub fn read_type_mask(&self, before: Box<dyn GpuFuture>) -> Result<Vec<u8>, TransferError> {
+        gpu::download_image(&self.context, storage_image(&self.type_mask), before)
+    }
+
+    /// Waits for `before` and returns the forces on the obstacles for every step recorded
+    /// since the last call, oldest first.
+    ///
+    /// Only the last [`FORCE_HISTORY`] steps are kept, so this has to be called at least that
+    /// often to get a gapless time series. See [`crate::forces`] for the obstacle indices.
+    pub fn read_forces(
+        &mut self,
+        before: Box<dyn GpuFuture>,
+    ) -> Result<Vec<ForceSample>, TransferError> {
+        before.then_signal_fence_and_flush()?.wait(None)?;
+
+        let values = self.forces.read()?;
+
+        let first = self
+            .forces_read
+            .max(self.forces_recorded.saturating_sub(FORCE_HISTORY as u64));
+
+        let samples = (first..self.forces_recorded)
+            .map(|recorded| {
+                let slot = (recorded % FORCE_HISTORY as u64) as usize;
+
+                let obstacles = (0..self.obstacles)
+                    .map(|obstacle| force_in_slot(&values, slot, obstacle))
+                    .collect();
+
+                ForceSample {
+                    step: self.force_steps[slot],
+                    obstacles,
+                }
+            })
+            .collect();
+
+        self.forces_read = self.forces_recorded;
+
+        Ok(samples)
+    }
+
+    /// The moving bodies at their pose during the most recent step.
+    pub fn bodies(&self) -> &[RigidBody] {
+        &self.bodies
+    }
+
+    /// Waits for `before`, which has to include the previous step, and advances the bodies
+    /// with the forces measured during it.
+    fn advance_bodies(
+        &mut self,
+        before: Box<dyn GpuFuture>,
+    ) -> Result<Box<dyn GpuFuture>, TransferError> {
+        before.then_signal_fence_and_flush()?.wait(None)?;
+
+        let values = self.forces.read()?;
+        let slot = ((self.forces_recorded - 1) % FORCE_HISTORY as u64) as usize;
+
+        for body in &mut self.bodies {
+            body.advance(
+                &force_in_slot(&values, slot, body.obstacle),
+                self.parameters.gravity,
+            );
+        }
+
+        Ok(sync::now(self.context.device()).boxed())
+    }
+
+    /// Downloads the lattice and derives the macroscopic fields from it.
+    pub fn read_fields(
+        &self,
+        before: Box<dyn GpuFuture>,
+        with_stress: bool,
+    ) -> Result<Fields, TransferError> {
+        let distributions = self.read_distributions(before)?;
+        let type_mask = self.read_type_mask(sync::now(self.context.device()).boxed())?;
+
+        let mut fields = Fields::from_distributions(self.size, distributions, type_mask);
+
+        let force_field = self
+            .initial_conditions
+            .as_ref()
+            .map(|initial_conditions| &initial_conditions.force[..]);
+
+        if self.parameters.thermal.is_some() {
+            let thermal_distributions =
+                self.read_thermal_distributions(sync::now(self.context.device()).boxed())?;
+
+            fields.compute_temperature(&thermal_distributions);
+        }
+
+        if self.parameters.dye.is_some() {
+            let dye_distributions =
+                self.read_dye_distributions(sync::now(self.context.device()).boxed())?;
+
+            fields.compute_dye(&dye_distributions);
+        }
+
+        if self.parameters.multiphase.has_second_component() {
+            let second_distributions =
+                self.read_second_distributions(sync::now(self.context.device()).boxed())?;
+
+            fields.compute_second_density(&second_distributions);
+        }
+
+        if self.parameters.free_surface.is_some() {
+            let surface = self.read_surface(sync::now(self.context.device()).boxed())?;
+
+            fields.compute_fill(&surface);
+        }
+
+        fields.apply_force(&self.parameters, force_field);
+
+        if with_stress {
+            fields.compute_stress_with(&self.parameters);
+        }
+
+        Ok(fields)
+    }
+
+    pub fn context(&self) -> &Context {
+        &self.context
+    }
+}
+
+/// Force on an obstacle as stored in a slot of the force buffer.
+fn force_in_slot(values: &[u32], slot: usize, obstacle: usize) -> ObstacleForce {
+    let base = (slot * MAX_OBSTACLES + obstacle) * 4;
+    let value = |component: usize| f32::from_bits(values[base + component]);
+
+    ObstacleForce {
+        force: [value(0), value(1)],
+        moment: value(2),
+    }
+}
+
+/// The image behind `view`, which transfers copy from and to.
+pub(crate) fn storage_image(view: &LatticeView) -> Arc<StorageImage> {
+    ImageView::image(view).clone()
+}
+
+pub(crate) fn create_image(
+    context: &Context,
+    size: [u32; 2],
+    array_layers: u32,
+    format: Format,
+) -> Result<LatticeView, SimulationCreationError> {
+    let image = StorageImage::with_usage(
+        context.device(),
+        ImageDimensions::Dim2d {
+            width: size[0],
+            height: size[1],
+            array_layers,
+        },
+        format,
+        ImageUsage {
+            sampled: true,
+            storage: true,
+            transfer_source: true,
+            transfer_destination: true,
+            ..ImageUsage::none()
+        },
+        ImageCreateFlags::none(),
+        [context.queue().family()],
+    )?;
+
+    Ok(ImageView::new(image)?)
+}
@@ -0,0 +1,303 @@
+use std::sync::Arc;
+
+use vulkano::buffer::CpuBufferPool;
+use vulkano::format::Format;
+use vulkano::image::ImageViewAbstract;
+use vulkano::sync::{self, GpuFuture};
+
+use crate::fields3d::{Fields3d, InitialConditions3d};
+use crate::gpu::{self, ComputeProgram, Context, TransferError};
+use crate::lattice::Q_THERMAL;
+use crate::lattice3d::VelocitySet;
+use crate::parameters::Parameters3d;
+use crate::simulation::{
+    create_image, storage_image, LatticeView, SimulationCreationError, StepError,
+};
+
+mod cs {
+    vulkano_shaders::shader! {
+        ty: "compute",
+        path: "src/shaders/main3d.comp"
+    }
+}
+
+/// A D3Q19 or D3Q27 lattice living on the GPU.
+///
+/// The populations live in two image arrays with one layer per population, which are swapped
+/// after every step. The z slices of the lattice are stacked along y in every image, so
+/// `height * depth` has to fit the largest 2D image the device supports, otherwise creating
+/// the simulation fails with [`SimulationCreationError::VolumeTooLarge`]. Every step
+/// also writes one z slice in the layout of the 2D lattice, which is what the renderer shows.
+pub struct Simulation3d {
+    context: Context,
+    program: ComputeProgram,
+    size: [u32; 3],
+    velocity_set: VelocitySet,
+    lattice: [LatticeView; 2],
+    type_mask: LatticeView,
+    cell_data: LatticeView,
+    slice_lattice: LatticeView,
+    slice_type_mask: LatticeView,
+    slice_dye: LatticeView,
+    current: usize,
+    steps: u64,
+    init: bool,
+    initial_conditions: Option<InitialConditions3d>,
+    parameters: Parameters3d,
+    parameter_pool: CpuBufferPool<cs::ty::Parameters>,
+    push_constants: cs::ty::PushConstants,
+}
+
+impl Simulation3d {
+    pub fn new(
+        context: &Context,
+        size: [u32; 3],
+        velocity_set: VelocitySet,
+    ) -> Result<Self, SimulationCreationError> {
+        let shader = cs::Shader::load(context.device())?;
+        let program = ComputeProgram::new(context, &shader.main_entry_point())?;
+
+        let q = velocity_set.q() as u32;
+
+        let lattice = [
+            create_volume(context, size, q, Format::R32_SFLOAT)?,
+            create_volume(context, size, q, Format::R32_SFLOAT)?,
+        ];
+
+        let type_mask = create_volume(context, size, 1, Format::R8_UINT)?;
+        let cell_data = create_volume(context, size, 1, Format::R32G32B32A32_SFLOAT)?;
+
+        let slice_size = [size[0], size[1]];
+
+        let slice_lattice = create_image(context, slice_size, 9, Format::R32_SFLOAT)?;
+        let slice_type_mask = create_image(context, slice_size, 1, Format::R8_UINT)?;
+        let slice_dye = create_image(context, slice_size, Q_THERMAL as u32, Format::R32_SFLOAT)?;
+
+        Ok(Self {
+            context: context.clone(),
+            program,
+            size,
+            velocity_set,
+            lattice,
+            type_mask,
+            cell_data,
+            slice_lattice,
+            slice_type_mask,
+            slice_dye,
+            current: 0,
+            steps: 0,
+            init: true,
+            initial_conditions: None,
+            parameters: Parameters3d::default(),
+            parameter_pool: CpuBufferPool::uniform_buffer(context.device()),
+            push_constants: cs::ty::PushConstants { slice: size[2] / 2 },
+        })
+    }
+
+    /// Records one collide-and-stream step after `before` and swaps the lattice images.
+    pub fn step(&mut self, before: Box<dyn GpuFuture>) -> Result<Box<dyn GpuFuture>, StepError> {
+        let mut before = before;
+
+        if self.init {
+            before = self.upload(before)?;
+            self.init = false;
+        }
+
+        let input = self.lattice[self.current].clone();
+        let output = self.lattice[1 - self.current].clone();
+
+        let boundaries = self.parameters.boundaries;
+        let [gx, gy, gz] = self.parameters.gravity;
+        let [fx, fy, fz] = self.parameters.force;
+
+        let parameters = Arc::new(self.parameter_pool.next(cs::ty::Parameters {
+            face_values: boundaries.values(),
+            face_types: boundaries.kinds(),
+            gravity: [gx, gy, gz, 0.0],
+            force: [fx, fy, fz, 0.0],
+            q: self.velocity_set.q() as u32,
+            beta: self.parameters.beta,
+            depth: self.size[2],
+        })?);
+
+        let images: Vec<Arc<dyn ImageViewAbstract>> = vec![
+            input,
+            output,
+            self.type_mask.clone(),
+            self.cell_data.clone(),
+            self.slice_lattice.clone(),
+            self.slice_type_mask.clone(),
+        ];
+
+        let dispatch = [
+            self.size[0] / 4 + 1,
+            self.size[1] / 4 + 1,
+            self.size[2] / 4 + 1,
+        ];
+
+        let future = self.program.compute_with_buffers(
+            &images,
+            &[parameters],
+            dispatch,
+            self.push_constants,
+            before,
+        )?;
+
+        self.current = 1 - self.current;
+        self.steps += 1;
+
+        Ok(future)
+    }
+
+    /// Reinitializes the lattice and the type mask on the next step.
+    ///
+    /// Uses the uploaded initial conditions if there are any, a domain at rest otherwise.
+    pub fn reset(&mut self) {
+        self.init = true;
+        self.steps = 0;
+    }
+
+    /// Number of steps recorded since the last reset.
+    pub fn steps(&self) -> u64 {
+        self.steps
+    }
+
+    /// Replaces the domain at rest the simulation starts with and resets it.
+    pub fn set_initial_conditions(&mut self, initial_conditions: InitialConditions3d) {
+        assert_eq!(initial_conditions.size, self.size);
+
+        self.initial_conditions = Some(initial_conditions);
+        self.reset();
+    }
+
+    fn upload(&self, before: Box<dyn GpuFuture>) -> Result<Box<dyn GpuFuture>, TransferError> {
+        let initial_conditions = self
+            .initial_conditions
+            .clone()
+            .unwrap_or_else(|| InitialConditions3d::new(self.size));
+
+        let future = gpu::upload_image(
+            &self.context,
+            initial_conditions.distributions(self.velocity_set),
+            storage_image(&self.lattice[self.current]),
+            before,
+        )?;
+
+        let future = gpu::upload_image(
+            &self.context,
+            initial_conditions.type_mask.clone(),
+            storage_image(&self.type_mask),
+            future,
+        )?;
+
+        let future = gpu::upload_image(
+            &self.context,
+            initial_conditions.cell_data(),
+            storage_image(&self.cell_data),
+            future,
+        )?;
+
+        // There is no dye in 3D, the renderer gets an empty field.
+        gpu::upload_image(
+            &self.context,
+            vec![0.0f32; (self.size[0] * self.size[1]) as usize * Q_THERMAL],
+            storage_image(&self.slice_dye),
+            future,
+        )
+    }
+
+    pub fn size(&self) -> [u32; 3] {
+        self.size
+    }
+
+    pub fn velocity_set(&self) -> VelocitySet {
+        self.velocity_set
+    }
+
+    pub fn parameters(&self) -> Parameters3d {
+        self.parameters
+    }
+
+    /// Takes effect with the next step.
+    pub fn set_parameters(&mut self, parameters: Parameters3d) {
+        self.parameters = parameters;
+    }
+
+    /// Selects the z index of the slice written by the following steps.
+    pub fn set_slice(&mut self, slice: u32) {
+        self.push_constants.slice = slice.min(self.size[2] - 1);
+    }
+
+    pub fn slice(&self) -> u32 {
+        self.push_constants.slice
+    }
+
+    /// The slice written by the most recent step as D2Q9 distributions, in the layout of
+    /// [`Simulation::lattice`](crate::Simulation::lattice).
+    pub fn lattice(&self) -> LatticeView {
+        self.slice_lattice.clone()
+    }
+
+    /// Cell types of the slice written by the most recent step.
+    pub fn type_mask(&self) -> LatticeView {
+        self.slice_type_mask.clone()
+    }
+
+    /// An empty dye field of the size of the slice.
+    pub fn dye(&self) -> LatticeView {
+        self.slice_dye.clone()
+    }
+
+    /// Downloads the distributions written by the most recent step, stored as `[i][z][y][x]`.
+    pub fn read_distributions(
+        &self,
+        before: Box<dyn GpuFuture>,
+    ) -> Result<Vec<f32>, TransferError> {
+        gpu::download_image(
+            &self.context,
+            storage_image(&self.lattice[self.current]),
+            before,
+        )
+    }
+
+    pub fn read_type_mask(&self, before: Box<dyn GpuFuture>) -> Result<Vec<u8>, TransferError> {
+        gpu::download_image(&self.context, storage_image(&self.type_mask), before)
+    }
+
+    /// Downloads the lattice and derives the macroscopic fields from it.
+    pub fn read_fields(&self, before: Box<dyn GpuFuture>) -> Result<Fields3d, TransferError> {
+        let distributions = self.read_distributions(before)?;
+        let type_mask = self.read_type_mask(sync::now(self.context.device()).boxed())?;
+
+        let mut fields =
+            Fields3d::from_distributions(self.size, self.velocity_set, distributions, type_mask);
+
+        fields.apply_force(&self.parameters);
+
+        Ok(fields)
+    }
+
+    pub fn context(&self) -> &Context {
+        &self.context
+    }
+}
+
+/// An image holding `layers` values per cell, with the z slices stacked along y.
+fn create_volume(
+    context: &Context,
+    size: [u32; 3],
+    layers: u32,
+    format: Format,
+) -> Result<LatticeView, SimulationCreationError> {
+    let dimensions = [size[0], size[1].saturating_mul(size[2])];
+    let max = context
+        .physical_device()
+        .properties()
+        .max_image_dimension2_d;
+
+    if dimensions.iter().any(|&dimension| dimension > max) {
+        return Err(SimulationCreationError::VolumeTooLarge(dimensions, max));
+    }
+
+    create_image(context, dimensions, layers, format)
+}
@@ -0,0 +1,39 @@
+//! Parameters of the temperature field, advected by the flow on a D2Q5 lattice and coupled
+//! back through a Boussinesq buoyancy force.
+
+use serde::Deserialize;
+
+#[derive(Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(default)]
+pub struct Thermal {
+    /// Thermal diffusivity in lattice units, the temperature relaxes with
+    /// `1 / (3 * diffusivity + 0.5)`.
+    pub diffusivity: f32,
+    /// Buoyancy acceleration per unit of temperature above the reference temperature, i.e.
+    /// `-expansion * gravity` in the Boussinesq approximation.
+    pub buoyancy: [f32; 2],
+    /// Temperature without buoyancy.
+    pub reference_temperature: f32,
+    /// Temperature of `HotWall` cells.
+    pub hot_temperature: f32,
+    /// Temperature of `ColdWall` cells.
+    pub cold_temperature: f32,
+}
+
+impl Default for Thermal {
+    fn default() -> Self {
+        Self {
+            diffusivity: 0.02,
+            buoyancy: [0.0, -1e-4],
+            reference_temperature: 0.0,
+            hot_temperature: 1.0,
+            cold_temperature: -1.0,
+        }
+    }
+}
+
+impl Thermal {
+    pub fn omega(&self) -> f32 {
+        1.0 / (3.0 * self.diffusivity + 0.5)
+    }
+}
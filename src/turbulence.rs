@@ -0,0 +1,119 @@
+//! Subgrid turbulence models adding a local eddy viscosity to the relaxation.
+
+use serde::Deserialize;
+
+#[derive(Deserialize, Clone, Copy, Debug, Default, PartialEq)]
+#[serde(tag = "type", rename_all = "kebab-case")]
+pub enum Turbulence {
+    /// Direct simulation with the viscosity given by `beta`.
+    #[default]
+    None,
+    /// Smagorinsky model, the strain rate is taken from the non-equilibrium stress.
+    Smagorinsky {
+        #[serde(default = "default_smagorinsky_constant")]
+        constant: f32,
+    },
+    /// Wall-adapting local eddy viscosity (Nicoud & Ducros 1999), from the velocity gradient
+    /// of the neighbouring cells.
+    Wale {
+        #[serde(default = "default_wale_constant")]
+        constant: f32,
+    },
+}
+
+fn default_smagorinsky_constant() -> f32 {
+    0.1
+}
+
+fn default_wale_constant() -> f32 {
+    0.5
+}
+
+impl Turbulence {
+    /// Turbulence model constant used by the compute shader.
+    pub fn kind(&self) -> u32 {
+        match self {
+            Turbulence::None => 0,
+            Turbulence::Smagorinsky { .. } => 1,
+            Turbulence::Wale { .. } => 2,
+        }
+    }
+
+    pub fn constant(&self) -> f32 {
+        match *self {
+            Turbulence::None => 0.0,
+            Turbulence::Smagorinsky { constant } | Turbulence::Wale { constant } => constant,
+        }
+    }
+
+    /// Whether the model needs the velocity gradient.
+    pub fn needs_gradient(&self) -> bool {
+        matches!(self, Turbulence::Wale { .. })
+    }
+
+    /// Relaxation parameter including the eddy viscosity, see `effective_beta` in the shader.
+    ///
+    /// `stress` is the non-equilibrium momentum flux `[xx, xy, yy]`, `gradient[a][b]` the
+    /// derivative of velocity component `a` in direction `b`.
+    pub fn effective_beta(
+        &self,
+        beta: f32,
+        rho: f32,
+        stress: [f32; 3],
+        gradient: [[f32; 2]; 2],
+    ) -> f32 {
+        let tau = 0.5 / beta;
+
+        let tau = match *self {
+            Turbulence::None => tau,
+            Turbulence::Smagorinsky { constant } => {
+                let q = (2.0
+                    * (stress[0] * stress[0]
+                        + 2.0 * stress[1] * stress[1]
+                        + stress[2] * stress[2]))
+                    .sqrt();
+
+                0.5 * (tau + (tau * tau + 18.0 * constant * constant * q / rho).sqrt())
+            }
+            Turbulence::Wale { constant } => tau + 3.0 * wale_viscosity(constant, gradient),
+        };
+
+        0.5 / tau
+    }
+}
+
+/// Eddy viscosity of the WALE model. The planar velocity gradient is treated as a 3D one
+/// without out-of-plane components.
+fn wale_viscosity(constant: f32, g: [[f32; 2]; 2]) -> f32 {
+    let mut g2 = [[0.0; 2]; 2];
+
+    for a in 0..2 {
+        for b in 0..2 {
+            g2[a][b] = g[a][0] * g[0][b] + g[a][1] * g[1][b];
+        }
+    }
+
+    let trace = g2[0][0] + g2[1][1];
+
+    let mut ss = 0.0;
+    let mut sdsd = trace * trace / 9.0;
+
+    for a in 0..2 {
+        for b in 0..2 {
+            let s = 0.5 * (g[a][b] + g[b][a]);
+            let identity = if a == b { 1.0 } else { 0.0 };
+            let sd = 0.5 * (g2[a][b] + g2[b][a]) - identity * trace / 3.0;
+
+            ss += s * s;
+            sdsd += sd * sd;
+        }
+    }
+
+    let denominator = ss.powf(2.5) + sdsd.powf(1.25);
+
+    if denominator > 0.0 {
+        constant * constant * sdsd.powf(1.5) / denominator
+    } else {
+        0.0
+    }
+}
@@ -0,0 +1,367 @@
+//! Conversion between physical units and the lattice units the kernel works in.
+//!
+//! A setup is described by the physical viscosity (or the Reynolds number), a characteristic
+//! length and velocity and the domain size. The lattice resolution across the characteristic
+//! length and the lattice velocity corresponding to the physical one fix the grid spacing and
+//! the time step, everything else follows from them.
+
+use serde::Deserialize;
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+pub enum UnitError {
+    #[error("Exactly one of viscosity and reynolds has to be given.")]
+    ViscosityOrReynolds,
+    #[error("{0} has to be positive.")]
+    NotPositive(&'static str),
+}
+
+/// Conditions under which the lattice setup becomes inaccurate or unstable.
+#[derive(Error, Debug, Clone, Copy, PartialEq)]
+pub enum UnitWarning {
+    #[error("Mach number {0:.3} is above 0.3, compressibility errors will be large.")]
+    HighMach(f32),
+    #[error("tau = {0:.4} is close to 1/2, the flow is under-resolved for this Reynolds number.")]
+    LowTau(f32),
+    #[error("tau = {0:.4} is above 2, the flow is over-damped and walls lose accuracy.")]
+    HighTau(f32),
+}
+
+const MAX_MACH: f32 = 0.3;
+const MIN_TAU: f32 = 0.505;
+const MAX_TAU: f32 = 2.0;
+
+/// Physical description of a setup, the `[units]` table of a scene file.
+#[derive(Deserialize, Clone, Debug)]
+pub struct PhysicalSetup {
+    /// Kinematic viscosity in m²/s.
+    pub viscosity: Option<f32>,
+    /// Reynolds number, determines the viscosity if that isn't given.
+    pub reynolds: Option<f32>,
+    /// Characteristic length in m.
+    pub length: f32,
+    /// Characteristic velocity in m/s.
+    pub velocity: f32,
+    /// Domain width and height in m.
+    pub domain: [f32; 2],
+    /// Lattice cells across the characteristic length.
+    #[serde(default = "default_resolution")]
+    pub resolution: u32,
+    /// Lattice velocity corresponding to the characteristic velocity.
+    #[serde(default = "default_lattice_velocity")]
+    pub lattice_velocity: f32,
+    /// Fluid density in kg/m³, corresponds to a lattice density of 1.
+    #[serde(default = "default_density")]
+    pub density: f32,
+}
+
+fn default_resolution() -> u32 {
+    64
+}
+
+fn default_lattice_velocity() -> f32 {
+    0.05
+}
+
+fn default_density() -> f32 {
+    1000.0
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct UnitSystem {
+    /// Grid spacing in m.
+    dx: f32,
+    /// Time step in s.
+    dt: f32,
+    /// Physical kinematic viscosity in m²/s.
+    viscosity: f32,
+    density: f32,
+    length: f32,
+    velocity: f32,
+    lattice_velocity: f32,
+    size: [u32; 2],
+}
+
+impl UnitSystem {
+    pub fn new(setup: &PhysicalSetup) -> Result<Self, UnitError> {
+        let positive = |value: f32, name| {
+            if value > 0.0 {
+                Ok(value)
+            } else {
+                Err(UnitError::NotPositive(name))
+            }
+        };
+
+        let length = positive(setup.length, "length")?;
+        let velocity = positive(setup.velocity, "velocity")?;
+        let lattice_velocity = positive(setup.lattice_velocity, "lattice_velocity")?;
+        let density = positive(setup.density, "density")?;
+        let resolution = positive(setup.resolution as f32, "resolution")?;
+        let domain = [
+            positive(setup.domain[0], "domain")?,
+            positive(setup.domain[1], "domain")?,
+        ];
+
+        let viscosity = match (setup.viscosity, setup.reynolds) {
+            (Some(viscosity), None) => positive(viscosity, "viscosity")?,
+            (None, Some(reynolds)) => velocity * length / positive(reynolds, "reynolds")?,
+            _ => return Err(UnitError::ViscosityOrReynolds),
+        };
+
+        let dx = length / resolution;
+        let dt = lattice_velocity * dx / velocity;
+
+        let size = [
+            ((domain[0] / dx).round() as u32).max(1),
+            ((domain[1] / dx).round() as u32).max(1),
+        ];
+
+        Ok(Self {
+            dx,
+            dt,
+            viscosity,
+            density,
+            length,
+            velocity,
+            lattice_velocity,
+            size,
+        })
+    }
+
+    /// Grid spacing in m.
+    pub fn dx(&self) -> f32 {
+        self.dx
+    }
+
+    /// Time step in s.
+    pub fn dt(&self) -> f32 {
+        self.dt
+    }
+
+    /// Lattice size covering the domain.
+    pub fn size(&self) -> [u32; 2] {
+        self.size
+    }
+
+    /// Characteristic velocity in lattice units.
+    pub fn lattice_velocity(&self) -> f32 {
+        self.lattice_velocity
+    }
+
+    /// Characteristic length in cells.
+    pub fn lattice_length(&self) -> f32 {
+        self.length / self.dx
+    }
+
+    /// Kinematic viscosity in lattice units.
+    pub fn lattice_viscosity(&self) -> f32 {
+        self.to_lattice_diffusivity(self.viscosity)
+    }
+
+    /// Converts a diffusivity in m²/s, e.g. a kinematic viscosity, to lattice units.
+    pub fn to_lattice_diffusivity(&self, diffusivity: f32) -> f32 {
+        diffusivity * self.dt / (self.dx * self.dx)
+    }
+
+    /// Relaxation time of the BGK collision.
+    pub fn tau(&self) -> f32 {
+        3.0 * self.lattice_viscosity() + 0.5
+    }
+
+    /// Relaxation parameter of the kernel, `1 / (2 * tau)`.
+    pub fn beta(&self) -> f32 {
+        1.0 / (2.0 * self.tau())
+    }
+
+    pub fn reynolds(&self) -> f32 {
+        self.velocity * self.length / self.viscosity
+    }
+
+    /// Mach number of the characteristic velocity on the lattice, the speed of sound being
+    /// `1 / sqrt(3)`.
+    pub fn mach(&self) -> f32 {
+        self.lattice_velocity * 3f32.sqrt()
+    }
+
+    /// Converts a velocity in m/s to lattice units.
+    pub fn to_lattice_velocity(&self, velocity: [f32; 2]) -> [f32; 2] {
+        let scale = self.dt / self.dx;
+
+        [velocity[0] * scale, velocity[1] * scale]
+    }
+
+    /// Converts a velocity in lattice units to m/s.
+    pub fn to_physical_velocity(&self, velocity: [f32; 2]) -> [f32; 2] {
+        let scale = self.dx / self.dt;
+
+        [velocity[0] * scale, velocity[1] * scale]
+    }
+
+    /// Converts an acceleration in m/s² to lattice units.
+    pub fn to_lattice_acceleration(&self, acceleration: [f32; 2]) -> [f32; 2] {
+        let scale = self.dt * self.dt / self.dx;
+
+        [acceleration[0] * scale, acceleration[1] * scale]
+    }
+
+    /// Converts a force density in N/m³, e.g. a pressure gradient, to lattice units.
+    pub fn to_lattice_force_density(&self, force: [f32; 2]) -> [f32; 2] {
+        self.to_lattice_acceleration([force[0] / self.density, force[1] / self.density])
+    }
+
+    /// Converts a mass per metre of depth in kg/m to lattice units, in which the fluid has a
+    /// density of one.
+    pub fn to_lattice_mass(&self, mass: f32) -> f32 {
+        mass / (self.density * self.dx * self.dx)
+    }
+
+    /// Physical time in s after the given number of steps.
+    pub fn physical_time(&self, steps: u64) -> f64 {
+        steps as f64 * self.dt as f64
+    }
+
+    pub fn warnings(&self) -> Vec<UnitWarning> {
+        let mut warnings = Vec::new();
+
+        let mach = self.mach();
+        let tau = self.tau();
+
+        if mach > MAX_MACH {
+            warnings.push(UnitWarning::HighMach(mach));
+        }
+
+        if tau < MIN_TAU {
+            warnings.push(UnitWarning::LowTau(tau));
+        } else if tau > MAX_TAU {
+            warnings.push(UnitWarning::HighTau(tau));
+        }
+
+        warnings
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A cylinder of 10 cm in a 40 cm by 10 cm channel at 1 m/s.
+    fn channel(reynolds: f32) -> PhysicalSetup {
+        PhysicalSetup {
+            viscosity: None,
+            reynolds: Some(reynolds),
+            length: 0.1,
+            velocity: 1.0,
+            domain: [0.4, 0.1],
+            resolution: default_resolution(),
+            lattice_velocity: default_lattice_velocity(),
+            density: default_density(),
+        }
+    }
+
+    fn close(a: f32, b: f32) -> bool {
+        (a - b).abs() <= 1e-5 * a.abs().max(b.abs())
+    }
+
+    #[test]
+    fn reynolds_number_fixes_the_lattice_viscosity() {
+        let units = UnitSystem::new(&channel(1000.0)).unwrap();
+
+        assert_eq!(units.size(), [256, 64]);
+        assert!(close(units.lattice_length(), 64.0));
+        assert!(close(units.lattice_viscosity(), 0.05 * 64.0 / 1000.0));
+        assert!(close(units.reynolds(), 1000.0));
+        assert!(close(units.beta(), 1.0 / (2.0 * units.tau())));
+        assert!(units.warnings().is_empty());
+    }
+
+    #[test]
+    fn viscosity_gives_the_same_setup_as_reynolds() {
+        let setup = PhysicalSetup {
+            viscosity: Some(1e-4),
+            reynolds: None,
+            ..channel(0.0)
+        };
+
+        let from_viscosity = UnitSystem::new(&setup).unwrap();
+        let from_reynolds = UnitSystem::new(&channel(1000.0)).unwrap();
+
+        assert!(close(from_viscosity.tau(), from_reynolds.tau()));
+    }
+
+    #[test]
+    fn conversions_round_trip() {
+        let units = UnitSystem::new(&channel(1000.0)).unwrap();
+
+        let lattice = units.to_lattice_velocity([1.0, -0.5]);
+
+        assert!(close(lattice[0], units.lattice_velocity()));
+        assert!(close(lattice[1], -0.5 * units.lattice_velocity()));
+
+        let physical = units.to_physical_velocity(lattice);
+
+        assert!(close(physical[0], 1.0));
+        assert!(close(physical[1], -0.5));
+
+        // A column of fluid one cell wide and high weighs one in lattice units.
+        let cell_mass = default_density() * units.dx() * units.dx();
+
+        assert!(close(units.to_lattice_mass(cell_mass), 1.0));
+        assert!(close(units.physical_time(1000) as f32, 1000.0 * units.dt()));
+    }
+
+    #[test]
+    fn invalid_setups_are_rejected() {
+        let both = PhysicalSetup {
+            viscosity: Some(1e-4),
+            ..channel(1000.0)
+        };
+
+        assert!(matches!(
+            UnitSystem::new(&both),
+            Err(UnitError::ViscosityOrReynolds)
+        ));
+
+        let neither = PhysicalSetup {
+            reynolds: None,
+            ..channel(1000.0)
+        };
+
+        assert!(matches!(
+            UnitSystem::new(&neither),
+            Err(UnitError::ViscosityOrReynolds)
+        ));
+
+        let negative = PhysicalSetup {
+            length: -0.1,
+            ..channel(1000.0)
+        };
+
+        assert!(matches!(
+            UnitSystem::new(&negative),
+            Err(UnitError::NotPositive("length"))
+        ));
+    }
+
+    #[test]
+    fn unstable_setups_warn() {
+        let fast = PhysicalSetup {
+            lattice_velocity: 0.2,
+            ..channel(1000.0)
+        };
+
+        assert!(matches!(
+            UnitSystem::new(&fast).unwrap().warnings()[..],
+            [UnitWarning::HighMach(_)]
+        ));
+
+        assert!(matches!(
+            UnitSystem::new(&channel(1e6)).unwrap().warnings()[..],
+            [UnitWarning::LowTau(_)]
+        ));
+
+        assert!(matches!(
+            UnitSystem::new(&channel(1.0)).unwrap().warnings()[..],
+            [UnitWarning::HighTau(_)]
+        ));
+    }
+}